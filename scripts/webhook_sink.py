@@ -0,0 +1,56 @@
+"""Local stand-in for an alert webhook receiver.
+
+    python scripts/webhook_sink.py [--port 9009] [--secret KEY] [--fail N]
+
+Prints every delivery, checks x-aegis-signature when --secret is given, and
+answers 500 to the first N requests so retries can be exercised.
+"""
+import argparse
+import hashlib
+import hmac
+import json
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+args = None
+seen = 0
+
+
+def signature_ok(header, body):
+    parts = dict(p.split("=", 1) for p in header.split(",") if "=" in p)
+    mac = hmac.new(args.secret.encode(), parts.get("t", "").encode() + b"." + body, hashlib.sha256)
+    return hmac.compare_digest(mac.hexdigest(), parts.get("v1", ""))
+
+
+class Sink(BaseHTTPRequestHandler):
+    def do_POST(self):
+        global seen
+        seen += 1
+        body = self.rfile.read(int(self.headers.get("content-length", 0)))
+        status = 200
+        if seen <= args.fail:
+            status = 500
+        elif args.secret and not signature_ok(self.headers.get("x-aegis-signature", ""), body):
+            status = 401
+        payload = json.loads(body or b"{}")
+        print(json.dumps({
+            "status": status,
+            "delivery": self.headers.get("x-aegis-delivery"),
+            "alert": payload.get("alert"),
+            "rule": payload.get("threat", {}).get("rule"),
+            "count": payload.get("count"),
+            "suppressed": payload.get("suppressed"),
+        }), flush=True)
+        self.send_response(status)
+        self.end_headers()
+
+    def log_message(self, *_):
+        pass
+
+
+if __name__ == "__main__":
+    p = argparse.ArgumentParser()
+    p.add_argument("--port", type=int, default=9009)
+    p.add_argument("--secret")
+    p.add_argument("--fail", type=int, default=0)
+    args = p.parse_args()
+    HTTPServer(("127.0.0.1", args.port), Sink).serve_forever()
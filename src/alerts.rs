@@ -0,0 +1,371 @@
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::{
+    audit::AuditLedger,
+    threats::{Threat, ThreatBus},
+};
+
+/// One alert rule from `AEGIS_ALERTS_PATH`. A threat matches when it is at
+/// least `min_severity` and its rule is listed in `rules` (empty matches any;
+/// a trailing `*` matches a prefix). The rule fires once `min_count` matches
+/// fall within `window_secs`; the same threat rule from the same source and
+/// principal then stays quiet for `dedupe_secs`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(default = "default_min_severity")]
+    pub min_severity: String,
+    #[serde(default)]
+    pub rules: Vec<String>,
+    #[serde(default = "default_min_count")]
+    pub min_count: usize,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    #[serde(default = "default_dedupe_secs")]
+    pub dedupe_secs: u64,
+    pub webhook: String,
+    /// env var holding the HMAC key payloads are signed with; every rule
+    /// needs one so receivers can reject forged alerts
+    pub secret_env: String,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_min_severity() -> String {
+    "high".into()
+}
+fn default_min_count() -> usize {
+    1
+}
+fn default_window_secs() -> u64 {
+    60
+}
+fn default_dedupe_secs() -> u64 {
+    300
+}
+fn default_max_attempts() -> u32 {
+    5
+}
+
+#[derive(Debug, Deserialize)]
+struct AlertsFile {
+    rules: Vec<AlertRule>,
+}
+
+fn severity_rank(s: &str) -> u8 {
+    match s {
+        "low" => 1,
+        "medium" => 2,
+        "high" => 3,
+        "critical" => 4,
+        _ => 0,
+    }
+}
+
+impl AlertRule {
+    fn matches(&self, t: &Threat) -> bool {
+        severity_rank(&t.severity) >= severity_rank(&self.min_severity)
+            && (self.rules.is_empty()
+                || self.rules.iter().any(|r| match r.strip_suffix('*') {
+                    Some(prefix) => t.rule.starts_with(prefix),
+                    None => t.rule == *r,
+                }))
+    }
+}
+
+struct RuleState {
+    rule: AlertRule,
+    secret: Vec<u8>,
+    hits: VecDeque<Instant>,
+    /// dedupe key -> (fired at, matches suppressed since)
+    fired: HashMap<String, (Instant, u64)>,
+}
+
+/// A webhook POST waiting to be made.
+struct Delivery {
+    id: String,
+    alert: String,
+    url: String,
+    secret: Vec<u8>,
+    max_attempts: u32,
+    body: String,
+    request_id: String,
+}
+
+pub struct Alerter {
+    rules: Vec<RuleState>,
+}
+
+impl Alerter {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+        let file: AlertsFile = serde_json::from_slice(&bytes)
+            .map_err(|e| format!("parse {}: {}", path.display(), e))?;
+        let mut rules = vec![];
+        for rule in file.rules {
+            if severity_rank(&rule.min_severity) == 0 {
+                return Err(format!(
+                    "alert {}: unknown severity {}",
+                    rule.name, rule.min_severity
+                ));
+            }
+            let secret = std::env::var(&rule.secret_env)
+                .ok()
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| format!("alert {}: {} is not set", rule.name, rule.secret_env))?
+                .into_bytes();
+            rules.push(RuleState {
+                rule,
+                secret,
+                hits: VecDeque::new(),
+                fired: HashMap::new(),
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    fn evaluate(&mut self, t: &Threat, now: Instant) -> Vec<Delivery> {
+        let mut out = vec![];
+        for st in &mut self.rules {
+            if !st.rule.matches(t) {
+                continue;
+            }
+            let window = Duration::from_secs(st.rule.window_secs);
+            st.hits.push_back(now);
+            while st
+                .hits
+                .front()
+                .is_some_and(|h| now.duration_since(*h) > window)
+            {
+                st.hits.pop_front();
+            }
+            if st.hits.len() < st.rule.min_count {
+                continue;
+            }
+            let dedupe = Duration::from_secs(st.rule.dedupe_secs);
+            let key = format!(
+                "{}|{}|{}",
+                t.rule,
+                t.src_ip,
+                t.principal.as_deref().unwrap_or("")
+            );
+            // the count of an expired entry is carried into the alert that replaces it
+            let suppressed = match st.fired.get_mut(&key) {
+                Some((at, n)) if now.duration_since(*at) < dedupe => {
+                    *n += 1;
+                    continue;
+                }
+                Some((_, n)) => *n,
+                None => 0,
+            };
+            // an expired entry with suppressed matches waits for its key's next alert
+            st.fired
+                .retain(|_, (at, n)| *n > 0 || now.duration_since(*at) < dedupe);
+            st.fired.insert(key, (now, 0));
+            let id = Uuid::new_v4().to_string();
+            let body = serde_json::json!({
+                "id": id,
+                "alert": st.rule.name,
+                "ts": OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+                "count": st.hits.len(),
+                "window_secs": st.rule.window_secs,
+                "suppressed": suppressed,
+                "threat": t,
+            });
+            out.push(Delivery {
+                id,
+                alert: st.rule.name.clone(),
+                url: st.rule.webhook.clone(),
+                secret: st.secret.clone(),
+                max_attempts: st.rule.max_attempts.max(1),
+                body: body.to_string(),
+                request_id: t.request_id.clone(),
+            });
+        }
+        out
+    }
+
+    /// Evaluates every threat published on `bus` and delivers alerts in the
+    /// background; outcomes are recorded in the ledger.
+    pub fn spawn(mut self, bus: &ThreatBus, ledger: Arc<AuditLedger>) {
+        let mut rx = bus.subscribe();
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_default();
+        tokio::spawn(async move {
+            loop {
+                let t = match rx.recv().await {
+                    Ok(t) => t,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!(missed = n, "alerts fell behind; threats not evaluated");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                for d in self.evaluate(&t, Instant::now()) {
+                    tokio::spawn(deliver(http.clone(), d, ledger.clone()));
+                }
+            }
+        });
+    }
+}
+
+/// `t=<unix secs>,v1=<hex HMAC-SHA256 of "<t>.<body>">`
+fn signature(secret: &[u8], ts: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(format!("{}.{}", ts, body).as_bytes());
+    format!("t={},v1={}", ts, hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs with exponential backoff. Network errors, 429 and 5xx are retried;
+/// any other status is final.
+async fn deliver(http: reqwest::Client, d: Delivery, ledger: Arc<AuditLedger>) {
+    let mut backoff = Duration::from_millis(500);
+    let mut last = String::new();
+    let mut attempts = 0;
+    while attempts < d.max_attempts {
+        attempts += 1;
+        let ts = OffsetDateTime::now_utc().unix_timestamp();
+        let req = http
+            .post(&d.url)
+            .header("content-type", "application/json")
+            .header("x-aegis-delivery", &d.id)
+            .header("x-aegis-signature", signature(&d.secret, ts, &d.body))
+            .body(d.body.clone());
+        let retry = match req.send().await {
+            Ok(res) if res.status().is_success() => {
+                let _ = ledger
+                    .append(
+                        "alert.delivered",
+                        &d.request_id,
+                        serde_json::json!({"alert": d.alert, "delivery_id": d.id, "attempts": attempts, "status": res.status().as_u16()}),
+                    )
+                    .await;
+                return;
+            }
+            Ok(res) => {
+                last = format!("status {}", res.status().as_u16());
+                res.status().is_server_error() || res.status().as_u16() == 429
+            }
+            Err(e) => {
+                last = e.to_string();
+                true
+            }
+        };
+        if !retry {
+            break;
+        }
+        if attempts < d.max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(30));
+        }
+    }
+    tracing::warn!(alert = %d.alert, delivery_id = %d.id, attempts, error = %last, "alert delivery failed");
+    let _ = ledger
+        .append(
+            "alert.failed",
+            &d.request_id,
+            serde_json::json!({"alert": d.alert, "delivery_id": d.id, "attempts": attempts, "error": last}),
+        )
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refire_reports_suppressed_matches() {
+        let rule: AlertRule = serde_json::from_value(serde_json::json!({
+            "name": "secrets",
+            "min_severity": "high",
+            "dedupe_secs": 60,
+            "webhook": "http://127.0.0.1:9/hook",
+            "secret_env": "AEGIS_ALERT_SECRET"
+        }))
+        .unwrap();
+        let mut alerter = Alerter {
+            rules: vec![RuleState {
+                rule,
+                secret: b"test".to_vec(),
+                hits: VecDeque::new(),
+                fired: HashMap::new(),
+            }],
+        };
+        let threat: Threat = serde_json::from_value(serde_json::json!({
+            "id": "t1", "ts": "2026-10-17T00:00:00Z", "severity": "critical",
+            "rule": "Deny: Secrets", "src_ip": "10.0.0.1", "dst_ip": "10.0.0.2",
+            "action": "blocked", "reason": "secret_detected"
+        }))
+        .unwrap();
+        let start = Instant::now();
+        let body = |d: &Delivery| serde_json::from_str::<serde_json::Value>(&d.body).unwrap();
+
+        let first = alerter.evaluate(&threat, start);
+        assert_eq!(first.len(), 1);
+        assert_eq!(body(&first[0])["suppressed"], 0);
+        for i in 1..=4 {
+            let quiet = alerter.evaluate(&threat, start + Duration::from_secs(i));
+            assert!(quiet.is_empty());
+        }
+        let again = alerter.evaluate(&threat, start + Duration::from_secs(61));
+        assert_eq!(again.len(), 1);
+        assert_eq!(body(&again[0])["suppressed"], 4);
+    }
+
+    #[test]
+    fn suppressed_count_survives_other_keys_firing() {
+        let rule: AlertRule = serde_json::from_value(serde_json::json!({
+            "name": "secrets",
+            "min_severity": "high",
+            "dedupe_secs": 60,
+            "webhook": "http://127.0.0.1:9/hook",
+            "secret_env": "AEGIS_ALERT_SECRET"
+        }))
+        .unwrap();
+        let mut alerter = Alerter {
+            rules: vec![RuleState {
+                rule,
+                secret: b"test".to_vec(),
+                hits: VecDeque::new(),
+                fired: HashMap::new(),
+            }],
+        };
+        let threat = |src_ip: &str| -> Threat {
+            serde_json::from_value(serde_json::json!({
+                "id": "t1", "ts": "2026-10-17T00:00:00Z", "severity": "critical",
+                "rule": "Deny: Secrets", "src_ip": src_ip, "dst_ip": "10.0.0.2",
+                "action": "blocked", "reason": "secret_detected"
+            }))
+            .unwrap()
+        };
+        let (a, b) = (threat("10.0.0.1"), threat("10.0.0.9"));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let body = |d: &Delivery| serde_json::from_str::<serde_json::Value>(&d.body).unwrap();
+
+        assert_eq!(alerter.evaluate(&a, at(0)).len(), 1);
+        assert_eq!(alerter.evaluate(&b, at(1)).len(), 1);
+        for i in 2..5 {
+            assert!(alerter.evaluate(&b, at(i)).is_empty());
+        }
+        // a's refire comes first and must not drop b's expired entry
+        let a_again = alerter.evaluate(&a, at(70));
+        assert_eq!(body(&a_again[0])["suppressed"], 0);
+        let b_again = alerter.evaluate(&b, at(71));
+        assert_eq!(b_again.len(), 1);
+        assert_eq!(body(&b_again[0])["suppressed"], 3);
+    }
+}
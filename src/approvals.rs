@@ -4,7 +4,7 @@ use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use crate::config::AppState;
+use crate::{config::AppState, metrics};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApprovalPayload {
@@ -30,19 +30,33 @@ fn canonical_payload_bytes(p: &ApprovalPayload) -> Vec<u8> {
     .unwrap_or_default()
 }
 
+pub fn signing_key_from_b64(sk_b64: &str) -> Option<SigningKey> {
+    let bytes = general_purpose::STANDARD.decode(sk_b64.trim()).ok()?;
+    let arr = <[u8; 32]>::try_from(bytes.as_slice()).ok()?;
+    Some(SigningKey::from_bytes(&arr))
+}
+
+pub fn verifying_key_from_b64(vk_b64: &str) -> Option<VerifyingKey> {
+    general_purpose::STANDARD
+        .decode(vk_b64.trim())
+        .ok()
+        .and_then(|b| VerifyingKey::from_bytes(&b.try_into().ok()?).ok())
+}
+
 fn derive_verifying_from_env() -> Option<VerifyingKey> {
-    if let Ok(sk_b64) = std::env::var("AEGIS_OPERATOR_SK_B64") {
-        if let Ok(bytes) = general_purpose::STANDARD.decode(sk_b64) {
-            if let Ok(arr) = <[u8; 32]>::try_from(bytes.as_slice()) {
-                let sk = SigningKey::from_bytes(&arr);
-                return Some(sk.verifying_key());
-            }
-        }
-    }
-    None
+    let sk_b64 = std::env::var("AEGIS_OPERATOR_SK_B64").ok()?;
+    signing_key_from_b64(&sk_b64).map(|sk| sk.verifying_key())
 }
 
 pub fn verify(token: &ApprovalToken, verifying_key_b64: &str) -> bool {
+    let ok = check(token, verifying_key_b64);
+    metrics::APPROVAL_VERIFICATIONS
+        .with_label_values(&[if ok { "valid" } else { "invalid" }])
+        .inc();
+    ok
+}
+
+fn check(token: &ApprovalToken, verifying_key_b64: &str) -> bool {
     let now = OffsetDateTime::now_utc().unix_timestamp();
     if token.payload.expires_at_unix < now {
         return false;
@@ -51,10 +65,7 @@ pub fn verify(token: &ApprovalToken, verifying_key_b64: &str) -> bool {
     let maybe_vk = if verifying_key_b64.trim().is_empty() {
         derive_verifying_from_env()
     } else {
-        general_purpose::STANDARD
-            .decode(verifying_key_b64)
-            .ok()
-            .and_then(|b| VerifyingKey::from_bytes(&b.try_into().ok()?).ok())
+        verifying_key_from_b64(verifying_key_b64)
     };
 
     if let Some(vk) = maybe_vk {
@@ -134,10 +145,19 @@ pub async fn sign_dev_approval(
         sig_b64: general_purpose::STANDARD.encode(sig.to_bytes()),
     };
 
-    st.ledger.append(
-        "approval.sign",
-        "DEV",
-        serde_json::json!({"scope": tok.payload.scope, "expires": tok.payload.expires_at_unix}),
-    );
+    if let Err(e) = st
+        .ledger
+        .append(
+            "approval.sign",
+            "DEV",
+            serde_json::json!({"scope": tok.payload.scope, "expires": tok.payload.expires_at_unix}),
+        )
+        .await
+    {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"error":"audit unavailable","detail":e.0})),
+        );
+    }
     (StatusCode::OK, Json(serde_json::json!({"token": tok})))
 }
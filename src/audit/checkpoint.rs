@@ -0,0 +1,129 @@
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+pub const CHECKPOINT_EVENT: &str = "audit.checkpoint";
+
+/// Short, stable identifier of a verifying key: the first 16 hex chars of its SHA-256.
+pub fn key_id(vk: &VerifyingKey) -> String {
+    let mut h = Sha256::new();
+    h.update(vk.as_bytes());
+    hex::encode(h.finalize())[..16].to_string()
+}
+
+fn signed_bytes(seq: u64, head_hash: &str, ts: &str, key_id: &str) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({
+      "seq": seq,
+      "head_hash": head_hash,
+      "ts": ts,
+      "key_id": key_id
+    }))
+    .unwrap_or_default()
+}
+
+fn tree_head_bytes(tree_size: u64, root_hash: &str, ts: &str, key_id: &str) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({
+      "tree_size": tree_size,
+      "root_hash": root_hash,
+      "ts": ts,
+      "key_id": key_id
+    }))
+    .unwrap_or_default()
+}
+
+pub struct CheckpointSigner {
+    key: SigningKey,
+    key_id: String,
+    pub every: u64,
+}
+
+impl CheckpointSigner {
+    pub fn new(key: SigningKey, every: u64) -> Self {
+        let key_id = key_id(&key.verifying_key());
+        Self {
+            key,
+            key_id,
+            every: every.max(1),
+        }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    /// Payload of a checkpoint record covering records `1..=seq`, the last of
+    /// which hashed to `head_hash`.
+    pub fn checkpoint(&self, seq: u64, head_hash: &str) -> serde_json::Value {
+        let ts = OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or_else(|_| "now".into());
+        let sig = self
+            .key
+            .sign(&signed_bytes(seq, head_hash, &ts, &self.key_id));
+        serde_json::json!({
+            "seq": seq,
+            "head_hash": head_hash,
+            "ts": ts,
+            "key_id": self.key_id,
+            "sig_b64": general_purpose::STANDARD.encode(sig.to_bytes()),
+        })
+    }
+
+    /// Signed tree head for the Merkle tree of the first `tree_size` records.
+    pub fn tree_head(&self, tree_size: u64, root_hash: &str) -> serde_json::Value {
+        let ts = OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap_or_else(|_| "now".into());
+        let sig = self
+            .key
+            .sign(&tree_head_bytes(tree_size, root_hash, &ts, &self.key_id));
+        serde_json::json!({
+            "tree_size": tree_size,
+            "root_hash": root_hash,
+            "ts": ts,
+            "key_id": self.key_id,
+            "sig_b64": general_purpose::STANDARD.encode(sig.to_bytes()),
+        })
+    }
+}
+
+/// Checks a checkpoint payload against the chain position it was found at and,
+/// when a verifying key is given, its signature.
+pub fn check(
+    payload: &serde_json::Value,
+    seq: u64,
+    head_hash: &str,
+    vk: Option<&VerifyingKey>,
+) -> Result<(), String> {
+    let cp_seq = payload["seq"].as_u64().ok_or("checkpoint without seq")?;
+    let cp_head = payload["head_hash"]
+        .as_str()
+        .ok_or("checkpoint without head_hash")?;
+    let ts = payload["ts"].as_str().ok_or("checkpoint without ts")?;
+    let cp_key = payload["key_id"]
+        .as_str()
+        .ok_or("checkpoint without key_id")?;
+    if cp_seq != seq {
+        return Err(format!("checkpoint seq {} but chain is at {}", cp_seq, seq));
+    }
+    if cp_head != head_hash {
+        return Err(format!(
+            "checkpoint head {} does not match chain head {}",
+            cp_head, head_hash
+        ));
+    }
+    let Some(vk) = vk else {
+        return Ok(());
+    };
+    if cp_key != key_id(vk) {
+        return Err(format!("checkpoint signed by unknown key {}", cp_key));
+    }
+    let sig = payload["sig_b64"]
+        .as_str()
+        .and_then(|s| general_purpose::STANDARD.decode(s).ok())
+        .and_then(|b| Signature::from_slice(&b).ok())
+        .ok_or("checkpoint signature malformed")?;
+    vk.verify_strict(&signed_bytes(cp_seq, cp_head, ts, cp_key), &sig)
+        .map_err(|_| "checkpoint signature invalid".to_string())
+}
@@ -0,0 +1,135 @@
+use std::{
+    io::{self, Write},
+    net::{TcpStream, UdpSocket},
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, SyncSender},
+    time::Duration,
+};
+
+use super::{
+    siem::{self, Format},
+    AuditEvent,
+};
+
+/// Where forwarded records go: `udp://host:port`, `tcp://host:port` or
+/// `unix:///dev/log`.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Udp(String),
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Target {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if let Some(addr) = s.strip_prefix("udp://") {
+            Ok(Self::Udp(addr.to_string()))
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            Ok(Self::Tcp(addr.to_string()))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            Ok(Self::Unix(PathBuf::from(path)))
+        } else {
+            Err(format!(
+                "syslog target {}: expected udp://, tcp:// or unix://",
+                s
+            ))
+        }
+    }
+}
+
+enum Conn {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Unix(UnixDatagram),
+}
+
+fn connect(target: &Target) -> io::Result<Conn> {
+    match target {
+        Target::Udp(addr) => {
+            let sock = UdpSocket::bind("0.0.0.0:0")?;
+            sock.connect(addr)?;
+            Ok(Conn::Udp(sock))
+        }
+        Target::Tcp(addr) => {
+            let stream = TcpStream::connect(addr)?;
+            stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+            Ok(Conn::Tcp(stream))
+        }
+        Target::Unix(path) => {
+            let sock = UnixDatagram::unbound()?;
+            sock.connect(path)?;
+            Ok(Conn::Unix(sock))
+        }
+    }
+}
+
+impl Conn {
+    fn send(&mut self, msg: &str) -> io::Result<()> {
+        match self {
+            Conn::Udp(s) => s.send(msg.as_bytes()).map(|_| ()),
+            // RFC 6587 octet counting, so payloads may contain newlines
+            Conn::Tcp(s) => s.write_all(format!("{} {}", msg.len(), msg).as_bytes()),
+            Conn::Unix(s) => s.send(msg.as_bytes()).map(|_| ()),
+        }
+    }
+}
+
+const QUEUE: usize = 4096;
+
+/// Ships every appended record to a syslog receiver from a background thread.
+/// The ledger never waits on the network: when the queue is full the record is
+/// dropped from forwarding (it is still in the ledger and can be re-exported).
+pub struct Forwarder {
+    tx: SyncSender<AuditEvent>,
+}
+
+impl Forwarder {
+    pub fn spawn(target: Target, format: Format) -> Self {
+        let (tx, rx) = mpsc::sync_channel(QUEUE);
+        std::thread::spawn(move || run(target, format, rx));
+        Self { tx }
+    }
+
+    pub fn forward(&self, ev: &AuditEvent) {
+        let _ = self.tx.try_send(ev.clone());
+    }
+}
+
+fn run(target: Target, format: Format, rx: Receiver<AuditEvent>) {
+    let mut conn: Option<Conn> = None;
+    let mut failing = false;
+    for ev in rx {
+        let msg = siem::render(format, &ev, ev.seq.unwrap_or(0));
+        // one reconnect per record; a receiver that stays down costs one
+        // connect attempt per record rather than blocking the queue
+        for _ in 0..2 {
+            let c = match conn.as_mut() {
+                Some(c) => c,
+                None => match connect(&target) {
+                    Ok(c) => conn.insert(c),
+                    Err(e) => {
+                        if !failing {
+                            tracing::warn!(?target, error = %e, "audit syslog forwarder: connect failed");
+                            failing = true;
+                        }
+                        break;
+                    }
+                },
+            };
+            match c.send(&msg) {
+                Ok(()) => {
+                    failing = false;
+                    break;
+                }
+                Err(e) => {
+                    if !failing {
+                        tracing::warn!(?target, error = %e, "audit syslog forwarder: send failed");
+                        failing = true;
+                    }
+                    conn = None;
+                }
+            }
+        }
+    }
+}
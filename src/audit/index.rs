@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    segment::{self, Manifest},
+    AuditEvent,
+};
+
+/// Where one ledger record lives and what it can be filtered by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub seq: u64,
+    /// byte offset of the record within its (uncompressed) segment
+    pub offset: u64,
+    pub len: u64,
+    pub event_type: String,
+    pub request_id: String,
+    pub ts_ms: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kinds: Vec<String>,
+}
+
+impl IndexEntry {
+    pub fn new(seq: u64, offset: u64, len: u64, ev: &AuditEvent) -> Self {
+        let mut kinds: Vec<String> = ev.payload["findings"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|f| f["kind"].as_str().map(str::to_string))
+            .collect();
+        kinds.sort();
+        kinds.dedup();
+        Self {
+            seq,
+            offset,
+            len,
+            event_type: ev.event_type.clone(),
+            request_id: ev.request_id.clone(),
+            ts_ms: ev.ts_ms(),
+            kinds,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub event_type: Option<String>,
+    pub request_id: Option<String>,
+    pub kind: Option<String>,
+    pub from_ms: Option<i64>,
+    pub to_ms: Option<i64>,
+    /// only entries strictly older than this seq (pagination cursor)
+    pub before_seq: Option<u64>,
+}
+
+impl Filter {
+    fn matches(&self, e: &IndexEntry) -> bool {
+        self.before_seq.is_none_or(|c| e.seq < c)
+            && self.event_type.as_ref().is_none_or(|t| &e.event_type == t)
+            && self.request_id.as_ref().is_none_or(|r| &e.request_id == r)
+            && self.kind.as_ref().is_none_or(|k| e.kinds.contains(k))
+            && self
+                .from_ms
+                .is_none_or(|from| e.ts_ms.is_some_and(|ts| ts >= from))
+            && self
+                .to_ms
+                .is_none_or(|to| e.ts_ms.is_some_and(|ts| ts < to))
+    }
+}
+
+/// In-memory index over the ledger, persisted as `<stem>.index.jsonl` next to it
+/// and rebuilt from the segments when missing or behind.
+pub struct AuditIndex {
+    path: PathBuf,
+    file: Option<File>,
+    /// lines not yet on disk because an earlier write failed
+    pending: String,
+    entries: Vec<IndexEntry>,
+    by_request: HashMap<String, Vec<usize>>,
+}
+
+impl AuditIndex {
+    pub fn load(active: &Path, manifest: &Manifest, head_seq: u64) -> io::Result<Self> {
+        let path = segment::index_path(active);
+        let floor = manifest.start().1;
+        let mut entries = vec![];
+        if let Ok(f) = File::open(&path) {
+            for line in BufReader::new(f).lines() {
+                if let Ok(e) = serde_json::from_str::<IndexEntry>(&line?) {
+                    entries.push(e);
+                }
+            }
+        }
+        let complete = entries.last().map(|e| e.seq).unwrap_or(floor) == head_seq;
+        let entries = if complete {
+            entries.retain(|e| e.seq > floor);
+            entries
+        } else {
+            rebuild(active, manifest)?
+        };
+        // rewrite so pruned and torn entries do not accumulate
+        let tmp = path.with_extension("jsonl.tmp");
+        {
+            let mut f = io::BufWriter::new(File::create(&tmp)?);
+            for e in &entries {
+                writeln!(f, "{}", serde_json::to_string(e).unwrap_or_default())?;
+            }
+            f.flush()?;
+        }
+        std::fs::rename(&tmp, &path)?;
+
+        let mut index = Self {
+            path,
+            file: None,
+            pending: String::new(),
+            entries: vec![],
+            by_request: HashMap::new(),
+        };
+        for e in entries {
+            index.insert(e);
+        }
+        Ok(index)
+    }
+
+    fn insert(&mut self, e: IndexEntry) {
+        self.by_request
+            .entry(e.request_id.clone())
+            .or_default()
+            .push(self.entries.len());
+        self.entries.push(e);
+    }
+
+    /// Adds `e` in memory and appends it to the index file. A failed write is
+    /// retried with the next record, so the file never skips an entry.
+    pub fn record(&mut self, e: IndexEntry) -> io::Result<()> {
+        self.pending
+            .push_str(&serde_json::to_string(&e).unwrap_or_default());
+        self.pending.push('\n');
+        self.insert(e);
+        if self.file.is_none() {
+            let f = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.file = Some(f);
+        }
+        let f = self.file.as_mut().unwrap();
+        let len = f.metadata()?.len();
+        if let Err(e) = f.write_all(self.pending.as_bytes()) {
+            let _ = f.set_len(len);
+            self.file = None;
+            return Err(e);
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Newest first. Returns the page and the cursor for the next one, if any.
+    pub fn query(
+        &self,
+        filter: &Filter,
+        floor_seq: u64,
+        limit: usize,
+    ) -> (Vec<IndexEntry>, Option<u64>) {
+        let candidates: Box<dyn Iterator<Item = &IndexEntry>> = match &filter.request_id {
+            Some(r) => Box::new(
+                self.by_request
+                    .get(r)
+                    .into_iter()
+                    .flatten()
+                    .rev()
+                    .map(|&i| &self.entries[i]),
+            ),
+            None => Box::new(self.entries.iter().rev()),
+        };
+        let mut page: Vec<IndexEntry> = candidates
+            .filter(|e| e.seq > floor_seq && filter.matches(e))
+            .take(limit + 1)
+            .cloned()
+            .collect();
+        let next = if page.len() > limit {
+            page.truncate(limit);
+            page.last().map(|e| e.seq)
+        } else {
+            None
+        };
+        (page, next)
+    }
+}
+
+fn rebuild(active: &Path, manifest: &Manifest) -> io::Result<Vec<IndexEntry>> {
+    let mut out = vec![];
+    let mut files: Vec<(PathBuf, u64)> = manifest
+        .segments
+        .iter()
+        .map(|s| (active.with_file_name(&s.file), s.first_seq))
+        .collect();
+    files.push((active.to_path_buf(), manifest.active.first_seq));
+    for (file, first_seq) in files {
+        let mut reader = BufReader::new(segment::open_segment(&file)?);
+        let mut seq = first_seq;
+        let mut offset = 0u64;
+        let mut buf = vec![];
+        loop {
+            buf.clear();
+            let n = reader.read_until(b'\n', &mut buf)? as u64;
+            if n == 0 {
+                break;
+            }
+            if !buf.iter().all(u8::is_ascii_whitespace) {
+                if let Ok(ev) = serde_json::from_slice::<AuditEvent>(&buf) {
+                    out.push(IndexEntry::new(seq, offset, n, &ev));
+                }
+                seq += 1;
+            }
+            offset += n;
+        }
+    }
+    Ok(out)
+}
+
+/// Reads the raw records for `entries` (ascending seq, all from `file`), opening
+/// the segment once and moving forward through it.
+pub fn read_records(file: &Path, entries: &[&IndexEntry]) -> io::Result<Vec<String>> {
+    let mut out = vec![];
+    let gz = file.extension().is_some_and(|e| e == "gz");
+    if !gz {
+        if let Ok(mut f) = File::open(file) {
+            for e in entries {
+                f.seek(SeekFrom::Start(e.offset))?;
+                let mut buf = vec![0u8; e.len as usize];
+                f.read_exact(&mut buf)?;
+                out.push(String::from_utf8_lossy(&buf).trim_end().to_string());
+            }
+            return Ok(out);
+        }
+    }
+    let mut r = segment::open_segment(file)?;
+    let mut pos = 0u64;
+    for e in entries {
+        io::copy(&mut (&mut r).take(e.offset - pos), &mut io::sink())?;
+        let mut buf = vec![0u8; e.len as usize];
+        r.read_exact(&mut buf)?;
+        pos = e.offset + e.len;
+        out.push(String::from_utf8_lossy(&buf).trim_end().to_string());
+    }
+    Ok(out)
+}
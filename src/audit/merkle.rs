@@ -0,0 +1,529 @@
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{segment::SegmentReader, AuditEvent};
+
+pub type Hash = [u8; 32];
+
+/// RFC 6962 leaf hash; the leaf data is the record's 32-byte chain hash.
+pub fn leaf_hash(event_hash: &Hash) -> Hash {
+    let mut h = Sha256::new();
+    h.update([0u8]);
+    h.update(event_hash);
+    h.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut h = Sha256::new();
+    h.update([1u8]);
+    h.update(left);
+    h.update(right);
+    h.finalize().into()
+}
+
+fn empty_root() -> Hash {
+    Sha256::digest([]).into()
+}
+
+/// Largest power of two strictly below `n` (n > 1).
+fn split(n: u64) -> u64 {
+    1 << (63 - (n - 1).leading_zeros())
+}
+
+/// RFC 6962 Merkle tree over the ledger, one leaf per record in `seq` order.
+/// Every complete, aligned power-of-two subtree is kept, so roots and proofs
+/// for any tree size cost O(log n) hashes.
+#[derive(Default)]
+pub struct MerkleTree {
+    /// `levels[k][i]` is the root of leaves `i*2^k .. (i+1)*2^k`
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn size(&self) -> u64 {
+        self.levels.first().map(|l| l.len() as u64).unwrap_or(0)
+    }
+
+    pub fn push(&mut self, event_hash: &Hash) {
+        self.push_leaf(leaf_hash(event_hash));
+    }
+
+    fn push_leaf(&mut self, mut node: Hash) {
+        let mut level = 0;
+        loop {
+            if self.levels.len() == level {
+                self.levels.push(vec![]);
+            }
+            self.levels[level].push(node);
+            let l = &self.levels[level];
+            if l.len() % 2 == 1 {
+                break;
+            }
+            node = node_hash(&l[l.len() - 2], &l[l.len() - 1]);
+            level += 1;
+        }
+    }
+
+    /// MTH(D[start..end]) for `start < end <= size`.
+    fn subtree(&self, start: u64, end: u64) -> Hash {
+        let n = end - start;
+        if n.is_power_of_two() {
+            let level = n.trailing_zeros() as usize;
+            return self.levels[level][(start >> level) as usize];
+        }
+        let k = split(n);
+        node_hash(
+            &self.subtree(start, start + k),
+            &self.subtree(start + k, end),
+        )
+    }
+
+    pub fn root(&self, size: u64) -> Option<Hash> {
+        match size {
+            0 => Some(empty_root()),
+            s if s <= self.size() => Some(self.subtree(0, s)),
+            _ => None,
+        }
+    }
+
+    /// Audit path for leaf `index` in the tree of the first `size` leaves
+    /// (RFC 6962 section 2.1.1), ordered from the leaf upwards.
+    pub fn inclusion_proof(&self, index: u64, size: u64) -> Option<Vec<Hash>> {
+        if index >= size || size > self.size() {
+            return None;
+        }
+        let mut proof = vec![];
+        self.path(index, 0, size, &mut proof);
+        Some(proof)
+    }
+
+    fn path(&self, m: u64, start: u64, end: u64, out: &mut Vec<Hash>) {
+        let n = end - start;
+        if n <= 1 {
+            return;
+        }
+        let k = split(n);
+        if m < k {
+            self.path(m, start, start + k, out);
+            out.push(self.subtree(start + k, end));
+        } else {
+            self.path(m - k, start + k, end, out);
+            out.push(self.subtree(start, start + k));
+        }
+    }
+
+    /// Proof that the tree of `old` leaves is a prefix of the tree of `new`
+    /// leaves (RFC 6962 section 2.1.2).
+    pub fn consistency_proof(&self, old: u64, new: u64) -> Option<Vec<Hash>> {
+        if old > new || new > self.size() {
+            return None;
+        }
+        let mut proof = vec![];
+        if old > 0 && old < new {
+            self.subproof(old, 0, new, true, &mut proof);
+        }
+        Some(proof)
+    }
+
+    fn subproof(&self, m: u64, start: u64, end: u64, whole: bool, out: &mut Vec<Hash>) {
+        let n = end - start;
+        if m == n {
+            if !whole {
+                out.push(self.subtree(start, end));
+            }
+            return;
+        }
+        let k = split(n);
+        if m <= k {
+            self.subproof(m, start, start + k, whole, out);
+            out.push(self.subtree(start + k, end));
+        } else {
+            self.subproof(m - k, start + k, end, false, out);
+            out.push(self.subtree(start, start + k));
+        }
+    }
+}
+
+/// The tree plus its leaves file, `<stem>.merkle`: the raw 32-byte hash of
+/// every record ever appended. It is never pruned, so the tree keeps covering
+/// records whose segments were deleted.
+pub struct MerkleLog {
+    path: PathBuf,
+    file: Option<File>,
+    /// leaves not yet on disk because an earlier write failed
+    pending: Vec<u8>,
+    pub tree: MerkleTree,
+}
+
+fn decode(hex_hash: &str) -> Option<Hash> {
+    hex::decode(hex_hash).ok()?.try_into().ok()
+}
+
+/// Leaf data for a ledger line: its chain hash, or the digest of the raw line
+/// for a record that no longer parses.
+fn line_leaf(line: &str) -> Hash {
+    serde_json::from_str::<AuditEvent>(line)
+        .ok()
+        .and_then(|ev| decode(&ev.hash))
+        .unwrap_or_else(|| Sha256::digest(line.as_bytes()).into())
+}
+
+impl MerkleLog {
+    /// Loads the leaves file and catches it up with the ledger: `head_seq`
+    /// records in total, the retained ones readable from `reader` starting
+    /// after `start_seq`.
+    pub fn load(
+        path: &Path,
+        head_seq: u64,
+        start_seq: u64,
+        reader: SegmentReader,
+    ) -> Result<Self, String> {
+        let mut leaves: Vec<Hash> = vec![];
+        let mut file_len = None;
+        match File::open(path) {
+            Ok(mut f) => {
+                let mut buf = vec![];
+                f.read_to_end(&mut buf)
+                    .map_err(|e| format!("read {}: {}", path.display(), e))?;
+                leaves.extend(buf.chunks_exact(32).map(|c| Hash::try_from(c).unwrap()));
+                file_len = Some(buf.len());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("read {}: {}", path.display(), e)),
+        }
+        leaves.truncate(head_seq as usize);
+        let have = leaves.len() as u64;
+        if have < head_seq {
+            if have < start_seq {
+                // ledgers pruned before the leaves file existed: those records are
+                // gone, so they become all-zero placeholder leaves
+                tracing::warn!(
+                    first = have + 1,
+                    last = start_seq,
+                    leaves = %path.display(),
+                    "audit merkle: records were pruned before the leaves file covered them; using placeholder leaves"
+                );
+                leaves.resize(start_seq as usize, [0u8; 32]);
+            }
+            let have = leaves.len() as u64;
+            let mut seq = start_seq;
+            for line in BufReader::new(reader).lines() {
+                let line = line.map_err(|e| format!("read audit ledger: {}", e))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                seq += 1;
+                if seq > have && seq <= head_seq {
+                    leaves.push(line_leaf(&line));
+                }
+            }
+        }
+        if file_len != Some(leaves.len() * 32) {
+            // also drops a torn trailing leaf or leaves past a truncated ledger
+            std::fs::write(path, leaves.concat())
+                .map_err(|e| format!("write {}: {}", path.display(), e))?;
+        }
+        let mut tree = MerkleTree::default();
+        for leaf in &leaves {
+            tree.push(leaf);
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: None,
+            pending: vec![],
+            tree,
+        })
+    }
+
+    /// Adds the leaf to the tree and appends it to the leaves file. A failed
+    /// write is retried with the next record, so the file never skips a leaf.
+    pub fn record(&mut self, event_hash: &str) -> io::Result<()> {
+        let leaf = decode(event_hash).unwrap_or_else(|| Sha256::digest(event_hash).into());
+        self.tree.push(&leaf);
+        self.pending.extend_from_slice(&leaf);
+        if self.file.is_none() {
+            let f = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&self.path)?;
+            self.file = Some(f);
+        }
+        let f = self.file.as_mut().unwrap();
+        let len = f.metadata()?.len();
+        if let Err(e) = f.write_all(&self.pending) {
+            let _ = f.set_len(len);
+            self.file = None;
+            return Err(e);
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the RFC 6962 reference inputs used by the Certificate Transparency test suites
+    const LEAVES: [&[u8]; 8] = [
+        b"",
+        b"\x00",
+        b"\x10",
+        b"\x20\x21",
+        b"\x30\x31",
+        b"\x40\x41\x42\x43",
+        b"\x50\x51\x52\x53\x54\x55\x56\x57",
+        b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+    ];
+
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn data_leaf(data: &[u8]) -> Hash {
+        let mut h = Sha256::new();
+        h.update([0u8]);
+        h.update(data);
+        h.finalize().into()
+    }
+
+    fn tree(leaves: &[&[u8]]) -> MerkleTree {
+        let mut t = MerkleTree::default();
+        for l in leaves {
+            t.push_leaf(data_leaf(l));
+        }
+        t
+    }
+
+    fn hashes(hex: &[&str]) -> Vec<Hash> {
+        hex.iter().map(|h| decode(h).unwrap()).collect()
+    }
+
+    /// RFC 9162 section 2.1.3.2
+    fn verify_inclusion(index: u64, size: u64, leaf: Hash, proof: &[Hash], root: &Hash) -> bool {
+        if index >= size {
+            return false;
+        }
+        let (mut f_n, mut s_n) = (index, size - 1);
+        let mut r = leaf;
+        for p in proof {
+            if s_n == 0 {
+                return false;
+            }
+            if f_n & 1 == 1 || f_n == s_n {
+                r = node_hash(p, &r);
+                while f_n & 1 == 0 && f_n != 0 {
+                    f_n >>= 1;
+                    s_n >>= 1;
+                }
+            } else {
+                r = node_hash(&r, p);
+            }
+            f_n >>= 1;
+            s_n >>= 1;
+        }
+        s_n == 0 && r == *root
+    }
+
+    /// RFC 9162 section 2.1.4.2
+    fn verify_consistency(
+        old: u64,
+        new: u64,
+        proof: &[Hash],
+        old_root: &Hash,
+        new_root: &Hash,
+    ) -> bool {
+        if old == new {
+            return proof.is_empty() && old_root == new_root;
+        }
+        if old == 0 || old > new || proof.is_empty() {
+            return false;
+        }
+        let mut path = proof.to_vec();
+        if old.is_power_of_two() {
+            path.insert(0, *old_root);
+        }
+        let (mut f_n, mut s_n) = (old - 1, new - 1);
+        while f_n & 1 == 1 {
+            f_n >>= 1;
+            s_n >>= 1;
+        }
+        let (mut fr, mut sr) = (path[0], path[0]);
+        for c in &path[1..] {
+            if s_n == 0 {
+                return false;
+            }
+            if f_n & 1 == 1 || f_n == s_n {
+                fr = node_hash(c, &fr);
+                sr = node_hash(c, &sr);
+                while f_n & 1 == 0 && f_n != 0 {
+                    f_n >>= 1;
+                    s_n >>= 1;
+                }
+            } else {
+                sr = node_hash(&sr, c);
+            }
+            f_n >>= 1;
+            s_n >>= 1;
+        }
+        s_n == 0 && fr == *old_root && sr == *new_root
+    }
+
+    #[test]
+    fn roots_match_reference_vectors() {
+        let t = tree(&LEAVES);
+        assert_eq!(t.root(0), Some(empty_root()));
+        for (n, root) in ROOTS.iter().enumerate() {
+            assert_eq!(hex::encode(t.root(n as u64 + 1).unwrap()), *root);
+        }
+        assert_eq!(t.root(9), None);
+    }
+
+    #[test]
+    fn inclusion_proofs_match_reference_vectors() {
+        let t = tree(&LEAVES);
+        let cases: [(u64, u64, &[&str]); 4] = [
+            (
+                0,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ],
+            ),
+            (
+                5,
+                8,
+                &[
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ],
+            ),
+            (
+                2,
+                3,
+                &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"],
+            ),
+            (
+                1,
+                5,
+                &[
+                    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ],
+            ),
+        ];
+        for (index, size, expected) in cases {
+            let proof = t.inclusion_proof(index, size).unwrap();
+            assert_eq!(proof, hashes(expected), "leaf {} of {}", index, size);
+        }
+        for size in 1..=8 {
+            let root = t.root(size).unwrap();
+            for index in 0..size {
+                let proof = t.inclusion_proof(index, size).unwrap();
+                let leaf = data_leaf(LEAVES[index as usize]);
+                assert!(verify_inclusion(index, size, leaf, &proof, &root));
+            }
+        }
+        assert_eq!(t.inclusion_proof(8, 8), None);
+    }
+
+    #[test]
+    fn consistency_proofs_match_reference_vectors() {
+        let t = tree(&LEAVES);
+        let cases: [(u64, u64, &[&str]); 4] = [
+            (1, 1, &[]),
+            (
+                1,
+                8,
+                &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ],
+            ),
+            (
+                6,
+                8,
+                &[
+                    "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ],
+            ),
+            (
+                2,
+                5,
+                &[
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ],
+            ),
+        ];
+        for (old, new, expected) in cases {
+            let proof = t.consistency_proof(old, new).unwrap();
+            assert_eq!(proof, hashes(expected), "{} -> {}", old, new);
+        }
+        for new in 1..=8 {
+            for old in 1..=new {
+                let proof = t.consistency_proof(old, new).unwrap();
+                let (a, b) = (t.root(old).unwrap(), t.root(new).unwrap());
+                assert!(
+                    verify_consistency(old, new, &proof, &a, &b),
+                    "{} -> {}",
+                    old,
+                    new
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_leaf_fails_both_proofs() {
+        let honest = tree(&LEAVES);
+        let mut leaves = LEAVES;
+        leaves[2] = b"\x11";
+        let forged = tree(&leaves);
+
+        // the forged leaf is not in the honest tree, with either tree's path
+        let root = honest.root(8).unwrap();
+        let leaf = data_leaf(leaves[2]);
+        for t in [&honest, &forged] {
+            let proof = t.inclusion_proof(2, 8).unwrap();
+            assert!(!verify_inclusion(2, 8, leaf, &proof, &root));
+        }
+        // and a tree rewritten below an old head is not consistent with it
+        let old_root = honest.root(4).unwrap();
+        let proof = forged.consistency_proof(4, 8).unwrap();
+        assert!(!verify_consistency(
+            4,
+            8,
+            &proof,
+            &old_root,
+            &forged.root(8).unwrap()
+        ));
+        // while a leaf after the old head may change freely
+        let proof = forged.consistency_proof(2, 8).unwrap();
+        assert!(verify_consistency(
+            2,
+            8,
+            &proof,
+            &honest.root(2).unwrap(),
+            &forged.root(8).unwrap()
+        ));
+    }
+}
@@ -0,0 +1,485 @@
+pub mod checkpoint;
+pub mod forward;
+pub mod index;
+pub mod merkle;
+pub mod segment;
+pub mod siem;
+pub mod verify;
+pub mod writer;
+
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+pub use checkpoint::CheckpointSigner;
+pub use forward::Forwarder;
+pub use index::Filter;
+pub use segment::RotationPolicy;
+pub use verify::{verify_file, VerifyReport};
+pub use writer::FsyncPolicy;
+
+use index::{AuditIndex, IndexEntry};
+use merkle::MerkleLog;
+use segment::{Manifest, SegmentReader};
+use tokio::sync::{mpsc, oneshot};
+use writer::{Head, Job, Writer};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// position in the chain, starting at 1; absent on records written before
+    /// these fields existed, which are hashed the old way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+    /// id of the HTTP exchange that caused the record, when there was one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_request_id: Option<String>,
+    pub event_type: String,
+    pub request_id: String,
+    pub payload: serde_json::Value,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+tokio::task_local! {
+    /// Set by the HTTP middleware for the lifetime of a request so every record
+    /// appended while serving it carries the caller's `x-request-id` (or the
+    /// minted id when none was sent) for correlation.
+    pub static HTTP_REQUEST_ID: String;
+    /// The gateway's own id for the request being served, the `request_id` its
+    /// records are appended under; outgoing calls carry it as `x-request-id`.
+    pub static REQUEST_ID: String;
+}
+
+/// Request id of records the ledger writes about itself (segments, checkpoints).
+pub const LEDGER_REQUEST_ID: &str = "AUDIT";
+
+/// What to do when the last record of an existing ledger does not check out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptTail {
+    Refuse,
+    NewSegment,
+}
+
+impl AuditEvent {
+    /// Hash over every field but `hash` itself. Legacy records (no `seq`) keep
+    /// the original four-field body so they still verify.
+    pub fn compute_hash(&self) -> String {
+        let mut body = serde_json::json!({
+            "event_type": self.event_type,
+            "request_id": self.request_id,
+            "payload": self.payload,
+            "prev_hash": self.prev_hash,
+        });
+        if let Some(seq) = self.seq {
+            body["seq"] = serde_json::json!(seq);
+            body["ts"] = serde_json::json!(self.ts);
+            body["instance_id"] = serde_json::json!(self.instance_id);
+            body["http_request_id"] = serde_json::json!(self.http_request_id);
+        }
+        let bytes = serde_json::to_vec(&body).unwrap_or_default();
+        let mut h = Sha256::new();
+        h.update(&bytes);
+        hex::encode(h.finalize())
+    }
+    pub fn hash_ok(&self) -> bool {
+        self.compute_hash() == self.hash
+    }
+    /// `ts` as unix milliseconds.
+    pub fn ts_ms(&self) -> Option<i64> {
+        let ts = OffsetDateTime::parse(self.ts.as_deref()?, &Rfc3339).ok()?;
+        Some((ts.unix_timestamp_nanos() / 1_000_000) as i64)
+    }
+}
+
+/// Returns the last non-empty line of the file, reading backwards in growing
+/// chunks so large ledgers are not loaded whole.
+fn read_tail_line(path: &Path) -> std::io::Result<Option<String>> {
+    let mut f = File::open(path)?;
+    let len = f.metadata()?.len();
+    let mut chunk = 64 * 1024u64;
+    loop {
+        let start = len.saturating_sub(chunk);
+        f.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![];
+        f.read_to_end(&mut buf)?;
+        let text = String::from_utf8_lossy(&buf);
+        let trimmed = text.trim_end();
+        match trimmed.rfind('\n') {
+            Some(i) => return Ok(Some(trimmed[i + 1..].to_string())),
+            None if start == 0 => {
+                return Ok((!trimmed.is_empty()).then(|| trimmed.to_string()));
+            }
+            None => chunk *= 2,
+        }
+    }
+}
+
+/// A crash mid-write can leave the file without its final newline; the next
+/// record must not be glued onto that fragment.
+fn terminate_torn_line(path: &Path) -> std::io::Result<()> {
+    let mut f = OpenOptions::new().read(true).append(true).open(path)?;
+    let len = f.metadata()?.len();
+    if len == 0 {
+        return Ok(());
+    }
+    let mut last = [0u8; 1];
+    f.seek(SeekFrom::Start(len - 1))?;
+    f.read_exact(&mut last)?;
+    if last[0] != b'\n' {
+        f.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn count_records(path: &Path) -> std::io::Result<u64> {
+    let f = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut n = 0;
+    for line in BufReader::new(f).split(b'\n') {
+        if !line?.iter().all(u8::is_ascii_whitespace) {
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+pub struct LedgerOptions {
+    pub on_corrupt: CorruptTail,
+    pub signer: Option<CheckpointSigner>,
+    /// Key used by `verify`; defaults to the signer's own verifying key.
+    pub verifying_key: Option<VerifyingKey>,
+    /// most records `verify` accepts after the last checkpoint
+    pub checkpoint_every: u64,
+    pub rotation: RotationPolicy,
+    pub instance_id: String,
+    pub forwarder: Option<Forwarder>,
+    pub fsync: FsyncPolicy,
+    /// records that may wait for the writer before `append` callers are held up
+    pub queue: usize,
+    /// `append` waits for the record to be written (and synced, per `fsync`)
+    /// and reports failures, instead of returning once it is queued
+    pub fail_closed: bool,
+}
+
+fn now_unix() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// The record could not be durably written; in fail-closed mode the request
+/// that caused it must not proceed.
+#[derive(Debug)]
+pub struct AuditError(pub String);
+
+impl std::fmt::Display for AuditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub struct AuditLedger {
+    path: PathBuf,
+    manifest: Arc<Mutex<Manifest>>,
+    index: Arc<Mutex<AuditIndex>>,
+    merkle: Arc<Mutex<MerkleLog>>,
+    signer: Option<Arc<CheckpointSigner>>,
+    verifying_key: Option<VerifyingKey>,
+    checkpoint_every: u64,
+    tx: mpsc::Sender<Job>,
+    fail_closed: bool,
+}
+impl AuditLedger {
+    /// Opens the ledger at `path` (the active segment), continuing the hash chain
+    /// from its last record or, right after a rotation, from the previous segment,
+    /// and starts the writer thread.
+    pub fn open(path: &Path, opts: LedgerOptions) -> Result<Self, String> {
+        let manifest_path = segment::manifest_path(path);
+        let mut manifest = Manifest::load(&manifest_path)
+            .map_err(|e| format!("read audit manifest: {}", e))?
+            .unwrap_or_else(|| Manifest::new(now_unix()));
+        segment::finish_rotation(path, &manifest)
+            .map_err(|e| format!("finish audit rotation: {}", e))?;
+        let pruned = segment::prune(&mut manifest, &opts.rotation, now_unix());
+        manifest
+            .save(&manifest_path)
+            .map_err(|e| format!("write audit manifest: {}", e))?;
+        segment::remove_pruned(path, pruned);
+
+        let tail = match read_tail_line(path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("read audit tail: {}", e)),
+        };
+        let records = count_records(path).map_err(|e| format!("count audit records: {}", e))?;
+        let seq = manifest.active.first_seq - 1 + records;
+        let last = tail.and_then(|line| serde_json::from_str::<AuditEvent>(&line).ok());
+        let (head, corrupt) = match &last {
+            None if records == 0 => (manifest.active.first_prev_hash.clone(), false),
+            Some(ev) if ev.hash_ok() => (ev.hash.clone(), false),
+            _ if opts.on_corrupt == CorruptTail::Refuse => {
+                return Err(format!(
+                    "audit ledger {} has a corrupt tail record; refusing to continue the chain",
+                    path.display()
+                ))
+            }
+            _ => {
+                terminate_torn_line(path).map_err(|e| format!("repair audit tail: {}", e))?;
+                ("GENESIS".to_string(), true)
+            }
+        };
+
+        let index = AuditIndex::load(path, &manifest, seq)
+            .map_err(|e| format!("load audit index: {}", e))?;
+        let merkle = MerkleLog::load(
+            &segment::merkle_path(path),
+            seq,
+            manifest.start().1,
+            SegmentReader::new(segment::chain_files(path, Some(&manifest))),
+        )?;
+        let verifying_key = opts
+            .verifying_key
+            .or_else(|| opts.signer.as_ref().map(|s| s.verifying_key()));
+        let signer = opts.signer.map(Arc::new);
+        let mut writer = Writer {
+            path: path.to_path_buf(),
+            manifest_path,
+            head: Head {
+                hash: head,
+                seq,
+                active_opened_at: manifest.active.opened_at,
+            },
+            manifest: Arc::new(Mutex::new(manifest)),
+            index: Arc::new(Mutex::new(index)),
+            merkle: Arc::new(Mutex::new(merkle)),
+            rotation: opts.rotation,
+            signer: signer.clone(),
+            instance_id: opts.instance_id,
+            forwarder: opts.forwarder,
+            fsync: opts.fsync,
+            file: None,
+            dirty: false,
+            // sign the head early after every start so no run leaves an unsigned tail
+            checkpoint_due: signer.is_some(),
+        };
+        if corrupt {
+            let previous_tail_hash = last.map(|ev| ev.hash);
+            writer
+                .write_record(
+                    "audit.segment.start",
+                    LEDGER_REQUEST_ID,
+                    None,
+                    serde_json::json!({
+                        "reason": "corrupt_tail",
+                        "previous_tail_hash": previous_tail_hash,
+                    }),
+                )
+                // sign the fresh segment right away instead of waiting for the interval
+                .and_then(|_| writer.write_checkpoint())
+                .and_then(|_| writer.sync())
+                .map_err(|e| format!("start new audit segment: {}", e))?;
+        }
+
+        let (tx, rx) = mpsc::channel(opts.queue.max(1));
+        if let FsyncPolicy::Interval(every) = opts.fsync {
+            writer::spawn_ticker(tx.downgrade(), every);
+        }
+        let ledger = Self {
+            path: writer.path.clone(),
+            manifest: writer.manifest.clone(),
+            index: writer.index.clone(),
+            merkle: writer.merkle.clone(),
+            signer,
+            verifying_key,
+            checkpoint_every: opts.checkpoint_every,
+            tx,
+            fail_closed: opts.fail_closed,
+        };
+        std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || writer.run(rx))
+            .map_err(|e| format!("start audit writer: {}", e))?;
+        Ok(ledger)
+    }
+    /// Hands the record to the writer. Waits while the queue is full; in
+    /// fail-closed mode also waits for the write and returns its outcome.
+    pub async fn append(
+        &self,
+        event_type: &str,
+        request_id: &str,
+        payload: serde_json::Value,
+    ) -> Result<(), AuditError> {
+        let (done, outcome) = if self.fail_closed {
+            let (tx, rx) = oneshot::channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        let job = Job::Append {
+            event_type: event_type.to_string(),
+            request_id: request_id.to_string(),
+            http_request_id: HTTP_REQUEST_ID.try_with(|id| id.clone()).ok(),
+            payload,
+            done,
+        };
+        let sent = self.tx.send(job).await;
+        if sent.is_err() {
+            crate::metrics::AUDIT_WRITE_FAILURES.inc();
+        }
+        let Some(outcome) = outcome else {
+            if sent.is_err() {
+                tracing::error!(event_type, "audit writer stopped; dropping event");
+            }
+            return Ok(());
+        };
+        sent.map_err(|_| AuditError("audit writer stopped".to_string()))?;
+        outcome
+            .await
+            .unwrap_or_else(|_| Err("audit writer stopped".to_string()))
+            .map_err(AuditError)
+    }
+    /// Waits until everything queued so far is written and synced.
+    pub async fn flush(&self) -> Result<(), AuditError> {
+        let (done, outcome) = oneshot::channel();
+        self.tx
+            .send(Job::Sync { done: Some(done) })
+            .await
+            .map_err(|_| AuditError("audit writer stopped".to_string()))?;
+        outcome
+            .await
+            .unwrap_or_else(|_| Err("audit writer stopped".to_string()))
+            .map_err(AuditError)
+    }
+    /// The whole retained chain, closed segments first, read lazily.
+    pub fn reader(&self) -> SegmentReader {
+        let m = self.manifest.lock().unwrap().clone();
+        SegmentReader::new(segment::chain_files(&self.path, Some(&m)))
+    }
+    /// Records matching `filter`, newest first, as parsed JSON with their `seq`,
+    /// plus the cursor to pass as `before_seq` for the next page.
+    pub fn query(
+        &self,
+        filter: &Filter,
+        limit: usize,
+    ) -> std::io::Result<(Vec<serde_json::Value>, Option<u64>)> {
+        let (page, next) = {
+            let floor = self.manifest.lock().unwrap().start().1;
+            self.index.lock().unwrap().query(filter, floor, limit)
+        };
+        let m = self.manifest.lock().unwrap().clone();
+        let file_of = |seq: u64| -> PathBuf {
+            m.segments
+                .iter()
+                .find(|s| s.first_seq <= seq && seq <= s.last_seq)
+                .map(|s| self.path.with_file_name(&s.file))
+                .unwrap_or_else(|| self.path.clone())
+        };
+        // read each segment once, front to back, then restore newest-first order
+        let mut ascending: Vec<&IndexEntry> = page.iter().rev().collect();
+        let mut events = Vec::with_capacity(page.len());
+        while !ascending.is_empty() {
+            let file = file_of(ascending[0].seq);
+            let split = ascending
+                .iter()
+                .position(|e| file_of(e.seq) != file)
+                .unwrap_or(ascending.len());
+            let rest = ascending.split_off(split);
+            for (e, raw) in ascending
+                .iter()
+                .zip(index::read_records(&file, &ascending)?)
+            {
+                let mut v: serde_json::Value =
+                    serde_json::from_str(&raw).unwrap_or_else(|_| serde_json::json!({"raw": raw}));
+                // legacy records carry no seq of their own
+                v["seq"] = serde_json::json!(e.seq);
+                events.push(v);
+            }
+            ascending = rest;
+        }
+        events.reverse();
+        Ok((events, next))
+    }
+    /// Tree head over the first `tree_size` records (default: all), signed when
+    /// a checkpoint signer is configured.
+    pub fn tree_head(&self, tree_size: Option<u64>) -> Result<serde_json::Value, String> {
+        let (size, root) = {
+            let m = self.merkle.lock().unwrap();
+            let size = tree_size.unwrap_or(m.tree.size());
+            let root = m
+                .tree
+                .root(size)
+                .ok_or_else(|| format!("tree size {} exceeds {}", size, m.tree.size()))?;
+            (size, hex::encode(root))
+        };
+        let mut sth = match &self.signer {
+            Some(signer) => signer.tree_head(size, &root),
+            None => serde_json::json!({
+                "tree_size": size,
+                "root_hash": root,
+                "ts": OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+                "key_id": null,
+                "sig_b64": null,
+            }),
+        };
+        sth["algorithm"] = serde_json::json!("rfc6962-sha256");
+        Ok(sth)
+    }
+    /// Audit path proving record `seq` is leaf `seq - 1` of the tree of size `tree_size`.
+    pub fn inclusion_proof(&self, seq: u64, tree_size: u64) -> Result<Vec<String>, String> {
+        let m = self.merkle.lock().unwrap();
+        m.tree
+            .inclusion_proof(seq.wrapping_sub(1), tree_size)
+            .map(|p| p.iter().map(hex::encode).collect())
+            .ok_or_else(|| format!("seq {} is not in a tree of size {}", seq, tree_size))
+    }
+    pub fn consistency_proof(&self, first: u64, second: u64) -> Result<Vec<String>, String> {
+        let m = self.merkle.lock().unwrap();
+        m.tree
+            .consistency_proof(first, second)
+            .map(|p| p.iter().map(hex::encode).collect())
+            .ok_or_else(|| {
+                format!(
+                    "no consistency proof from {} to {} (tree size {})",
+                    first,
+                    second,
+                    m.tree.size()
+                )
+            })
+    }
+    /// The retained chain rendered as `format`, read lazily; each line is
+    /// passed through `scrub` first.
+    pub fn export(
+        &self,
+        format: siem::Format,
+        scrub: impl Fn(&str) -> String + Send + 'static,
+    ) -> siem::SiemReader<BufReader<SegmentReader>> {
+        let start = self.manifest.lock().unwrap().start().1;
+        siem::SiemReader::new(
+            BufReader::new(self.reader()),
+            format,
+            start,
+            Box::new(scrub),
+        )
+    }
+    pub fn verify(&self) -> std::io::Result<VerifyReport> {
+        let start = self.manifest.lock().unwrap().start();
+        Ok(verify::verify_chain(
+            BufReader::new(self.reader()),
+            start,
+            self.verifying_key.as_ref(),
+            self.checkpoint_every,
+        ))
+    }
+}
@@ -0,0 +1,252 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// When the active segment is closed and how long closed segments are kept.
+/// Zero disables the respective limit.
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    pub max_bytes: u64,
+    pub max_age_secs: i64,
+    pub retain_segments: usize,
+    pub retain_days: i64,
+    pub compress: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    pub file: String,
+    pub first_seq: u64,
+    pub last_seq: u64,
+    pub first_prev_hash: String,
+    pub last_hash: String,
+    pub opened_at: i64,
+    pub closed_at: i64,
+    pub compressed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveSegment {
+    pub first_seq: u64,
+    pub first_prev_hash: String,
+    pub opened_at: i64,
+}
+
+/// Where the retained part of the chain starts once old segments were deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pruned {
+    pub through_seq: u64,
+    pub last_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub next_index: u64,
+    /// closed segments, oldest first
+    pub segments: Vec<SegmentInfo>,
+    pub active: ActiveSegment,
+    pub pruned: Option<Pruned>,
+}
+
+impl Manifest {
+    pub fn new(opened_at: i64) -> Self {
+        Self {
+            next_index: 1,
+            segments: vec![],
+            active: ActiveSegment {
+                first_seq: 1,
+                first_prev_hash: "GENESIS".to_string(),
+                opened_at,
+            },
+            pruned: None,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(b) => serde_json::from_slice(&b)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Written to a temp file and renamed so a crash never leaves half a manifest.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self).unwrap_or_default())?;
+        fs::rename(tmp, path)
+    }
+
+    /// Chain position the retained records continue from.
+    pub fn start(&self) -> (String, u64) {
+        match &self.pruned {
+            Some(p) => (p.last_hash.clone(), p.through_seq),
+            None => ("GENESIS".to_string(), 0),
+        }
+    }
+}
+
+/// `aegis_audit.jsonl` -> `aegis_audit.manifest.json`
+pub fn manifest_path(active: &Path) -> PathBuf {
+    active.with_file_name(format!("{}.manifest.json", stem(active)))
+}
+
+/// `aegis_audit.jsonl` -> `aegis_audit.index.jsonl`
+pub fn index_path(active: &Path) -> PathBuf {
+    active.with_file_name(format!("{}.index.jsonl", stem(active)))
+}
+
+/// `aegis_audit.jsonl` -> `aegis_audit.merkle`
+pub fn merkle_path(active: &Path) -> PathBuf {
+    active.with_file_name(format!("{}.merkle", stem(active)))
+}
+
+/// `aegis_audit.jsonl` -> `aegis_audit.000001.jsonl`
+pub fn closed_name(active: &Path, index: u64) -> String {
+    format!("{}.{:06}.jsonl", stem(active), index)
+}
+
+fn stem(active: &Path) -> String {
+    active
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "aegis_audit".to_string())
+}
+
+/// Every file of the chain in order: closed segments, then the active one.
+pub fn chain_files(active: &Path, manifest: Option<&Manifest>) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = manifest
+        .map(|m| {
+            m.segments
+                .iter()
+                .map(|s| active.with_file_name(&s.file))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.push(active.to_path_buf());
+    files
+}
+
+pub fn open_segment(path: &Path) -> io::Result<Box<dyn Read + Send>> {
+    let gz = path.extension().is_some_and(|e| e == "gz");
+    match File::open(path) {
+        Ok(f) if gz => Ok(Box::new(GzDecoder::new(f))),
+        Ok(f) => Ok(Box::new(f)),
+        // compressed since the file list was taken
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !gz => {
+            let mut p = path.as_os_str().to_owned();
+            p.push(".gz");
+            match File::open(PathBuf::from(p)) {
+                Ok(f) => Ok(Box::new(GzDecoder::new(f))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Box::new(io::empty())),
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Box::new(io::empty())),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads a list of segments back to back as one JSONL stream, opening each
+/// file only when the previous one is exhausted.
+pub struct SegmentReader {
+    files: VecDeque<PathBuf>,
+    current: Option<Box<dyn Read + Send>>,
+}
+
+impl SegmentReader {
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        Self {
+            files: files.into(),
+            current: None,
+        }
+    }
+}
+
+impl Read for SegmentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let current = match &mut self.current {
+                Some(r) => r,
+                None => {
+                    let Some(p) = self.files.pop_front() else {
+                        return Ok(0);
+                    };
+                    self.current.insert(open_segment(&p)?)
+                }
+            };
+            let n = current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            self.current = None;
+        }
+    }
+}
+
+/// Gzips a closed segment next to itself and removes the original.
+pub fn compress(path: &Path) -> io::Result<PathBuf> {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+    let gz_path = PathBuf::from(gz_path);
+    let tmp = gz_path.with_extension("gz.tmp");
+    {
+        let mut enc = GzEncoder::new(File::create(&tmp)?, Compression::default());
+        io::copy(&mut File::open(path)?, &mut enc)?;
+        enc.finish()?.sync_all()?;
+    }
+    fs::rename(&tmp, &gz_path)?;
+    fs::remove_file(path)?;
+    Ok(gz_path)
+}
+
+/// Drops the oldest closed segments beyond the retention limits from the
+/// manifest and records where the retained chain now starts. Returns their
+/// files, to be removed by [`remove_pruned`] once the manifest is saved.
+pub fn prune(manifest: &mut Manifest, policy: &RotationPolicy, now: i64) -> Vec<String> {
+    let mut dropped = vec![];
+    while let Some(oldest) = manifest.segments.first() {
+        let over_count =
+            policy.retain_segments > 0 && manifest.segments.len() > policy.retain_segments;
+        let too_old =
+            policy.retain_days > 0 && now - oldest.closed_at > policy.retain_days * 86_400;
+        if !over_count && !too_old {
+            break;
+        }
+        let seg = manifest.segments.remove(0);
+        manifest.pruned = Some(Pruned {
+            through_seq: seg.last_seq,
+            last_hash: seg.last_hash,
+        });
+        dropped.push(seg.file);
+    }
+    dropped
+}
+
+pub fn remove_pruned(active: &Path, files: Vec<String>) {
+    for file in files {
+        let _ = fs::remove_file(active.with_file_name(file));
+    }
+}
+
+/// Completes a rotation cut short after the manifest was saved: the newest
+/// closed segment has no file yet, its records are still in the active one.
+pub fn finish_rotation(active: &Path, manifest: &Manifest) -> io::Result<()> {
+    let Some(seg) = manifest.segments.last() else {
+        return Ok(());
+    };
+    let closed = active.with_file_name(&seg.file);
+    let mut gz = closed.as_os_str().to_owned();
+    gz.push(".gz");
+    if closed.exists() || PathBuf::from(gz).exists() || !active.exists() {
+        return Ok(());
+    }
+    fs::rename(active, closed)
+}
@@ -0,0 +1,448 @@
+use std::io::{self, BufRead, Read};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+
+use super::AuditEvent;
+
+/// Output formats for `/v1/aegis/export?format=` and the syslog forwarder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// the ledger lines untouched
+    Jsonl,
+    Ocsf,
+    Cef,
+    Syslog,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "" | "jsonl" | "raw" => Ok(Self::Jsonl),
+            "ocsf" => Ok(Self::Ocsf),
+            "cef" => Ok(Self::Cef),
+            "syslog" | "rfc5424" => Ok(Self::Syslog),
+            other => Err(format!("unknown export format {}", other)),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jsonl | Self::Ocsf => "application/x-ndjson",
+            Self::Cef | Self::Syslog => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+const VENDOR: &str = "Aegis";
+const PRODUCT: &str = "Aegis Ultra";
+const APP_NAME: &str = "aegis";
+/// RFC 5424 facility 13, "log audit"
+const FACILITY_LOG_AUDIT: u8 = 13;
+/// example private enterprise number from RFC 5612, used for our SD-ID
+const SD_ID: &str = "aegis@32473";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Informational,
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    fn ocsf(self) -> (u8, &'static str) {
+        match self {
+            Self::Informational => (1, "Informational"),
+            Self::Low => (2, "Low"),
+            Self::Medium => (3, "Medium"),
+            Self::High => (4, "High"),
+        }
+    }
+    fn cef(self) -> u8 {
+        match self {
+            Self::Informational => 1,
+            Self::Low => 3,
+            Self::Medium => 5,
+            Self::High => 8,
+        }
+    }
+    fn syslog(self) -> u8 {
+        match self {
+            Self::Informational => 6,
+            Self::Low => 5,
+            Self::Medium => 4,
+            Self::High => 3,
+        }
+    }
+}
+
+/// How one ledger event type is presented to a SIEM.
+struct Mapping {
+    class_uid: u32,
+    class_name: &'static str,
+    category_uid: u32,
+    category_name: &'static str,
+    activity_id: u32,
+    activity_name: &'static str,
+    severity: Severity,
+    /// what the gateway did: CEF `act`, OCSF `unmapped.action`, syslog `action`
+    action: &'static str,
+}
+
+const DETECTION_FINDING: (u32, &str, u32, &str) = (2004, "Detection Finding", 2, "Findings");
+const API_ACTIVITY: (u32, &str, u32, &str) = (6003, "API Activity", 6, "Application Activity");
+const APP_LIFECYCLE: (u32, &str, u32, &str) =
+    (6002, "Application Lifecycle", 6, "Application Activity");
+
+fn mapping(ev: &AuditEvent) -> Mapping {
+    let t = ev.event_type.as_str();
+    let has_findings = ev.payload["findings"]
+        .as_array()
+        .is_some_and(|f| !f.is_empty());
+    let denied = t.ends_with(".deny") || t.ends_with(".denied");
+    let ((class_uid, class_name, category_uid, category_name), activity_id, activity_name) =
+        if t.starts_with("prompt.") || t.starts_with("response.") {
+            // Detection Finding activities: 1 Create, 2 Update, 3 Close
+            (DETECTION_FINDING, 1, "Create")
+        } else if t.starts_with("audit.") {
+            (APP_LIFECYCLE, 99, "Other")
+        } else if t.starts_with("tool.commit") {
+            (API_ACTIVITY, 1, "Create")
+        } else if t.starts_with("tool.prepare") {
+            (API_ACTIVITY, 2, "Read")
+        } else if t.starts_with("approval.") {
+            (API_ACTIVITY, 3, "Update")
+        } else {
+            (API_ACTIVITY, 99, "Other")
+        };
+    let severity = if denied {
+        Severity::High
+    } else if t.ends_with(".error") {
+        Severity::Medium
+    } else if has_findings || t.ends_with(".redact") {
+        Severity::Low
+    } else {
+        Severity::Informational
+    };
+    let action = if denied {
+        "blocked"
+    } else if t.ends_with(".redact") || ev.payload["redacted"].as_bool() == Some(true) {
+        "redacted"
+    } else if t.ends_with(".error") {
+        "failed"
+    } else {
+        "allowed"
+    };
+    Mapping {
+        class_uid,
+        class_name,
+        category_uid,
+        category_name,
+        activity_id,
+        activity_name,
+        severity,
+        action,
+    }
+}
+
+fn kinds(ev: &AuditEvent) -> Vec<String> {
+    let mut kinds: Vec<String> = ev.payload["findings"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|f| f["kind"].as_str().map(str::to_string))
+        .collect();
+    kinds.sort();
+    kinds.dedup();
+    kinds
+}
+
+/// One OCSF 1.1 event object.
+pub fn ocsf(ev: &AuditEvent, seq: u64) -> serde_json::Value {
+    let m = mapping(ev);
+    let (severity_id, severity) = m.severity.ocsf();
+    let failed = m.action != "allowed" && m.action != "redacted";
+    let mut out = serde_json::json!({
+        "class_uid": m.class_uid,
+        "class_name": m.class_name,
+        "category_uid": m.category_uid,
+        "category_name": m.category_name,
+        "activity_id": m.activity_id,
+        "activity_name": m.activity_name,
+        "type_uid": m.class_uid as u64 * 100 + m.activity_id as u64,
+        "severity_id": severity_id,
+        "severity": severity,
+        "time": ev.ts_ms(),
+        "status_id": if failed { 2 } else { 1 },
+        "status": if failed { "Failure" } else { "Success" },
+        "message": ev.event_type,
+        "metadata": {
+            "version": "1.1.0",
+            "product": {"name": PRODUCT, "vendor_name": VENDOR, "version": env!("CARGO_PKG_VERSION")},
+            "uid": ev.hash,
+            "correlation_uid": ev.request_id,
+            "sequence": seq,
+            "original_time": ev.ts,
+            "log_name": "aegis_audit",
+        },
+        "unmapped": {
+            "event_type": ev.event_type,
+            "payload": ev.payload,
+            "prev_hash": ev.prev_hash,
+            "instance_id": ev.instance_id,
+            "http_request_id": ev.http_request_id,
+            "action": m.action,
+        },
+    });
+    if m.class_uid == DETECTION_FINDING.0 {
+        out["finding_info"] = serde_json::json!({
+            "uid": ev.hash,
+            "title": ev.event_type,
+            "types": kinds(ev),
+        });
+    } else {
+        out["api"] = serde_json::json!({
+            "operation": ev.event_type,
+            "request": {"uid": ev.request_id},
+        });
+    }
+    out
+}
+
+fn cef_header(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn cef_ext(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+/// One ArcSight CEF line (without syslog prefix).
+pub fn cef(ev: &AuditEvent, seq: u64) -> String {
+    let m = mapping(ev);
+    let mut ext = vec![
+        ("act", m.action.to_string()),
+        ("externalId", ev.request_id.clone()),
+        ("cn1Label", "seq".to_string()),
+        ("cn1", seq.to_string()),
+        ("cs1Label", "hash".to_string()),
+        ("cs1", ev.hash.clone()),
+    ];
+    if let Some(ts) = ev.ts_ms() {
+        ext.insert(0, ("rt", ts.to_string()));
+    }
+    if let Some(host) = &ev.instance_id {
+        ext.push(("dvchost", host.clone()));
+    }
+    if let Some(id) = &ev.http_request_id {
+        ext.push(("cs2Label", "http_request_id".to_string()));
+        ext.push(("cs2", id.clone()));
+    }
+    if let Some(reason) = ev.payload["reason"].as_str() {
+        ext.push(("reason", reason.to_string()));
+    }
+    let kinds = kinds(ev);
+    if !kinds.is_empty() {
+        ext.push(("cat", kinds.join(",")));
+    }
+    ext.push(("msg", ev.payload.to_string()));
+    let ext: Vec<String> = ext
+        .into_iter()
+        .map(|(k, v)| format!("{}={}", k, cef_ext(&v)))
+        .collect();
+    format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+        cef_header(VENDOR),
+        cef_header(PRODUCT),
+        env!("CARGO_PKG_VERSION"),
+        cef_header(&ev.event_type),
+        cef_header(&ev.event_type),
+        m.severity.cef(),
+        ext.join(" ")
+    )
+}
+
+fn sd_param(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// RFC 5424 header field: printable US-ASCII without spaces, `-` when empty.
+fn header_field(s: Option<&str>, max: usize) -> String {
+    let v: String = s
+        .unwrap_or("")
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if v.is_empty() {
+        "-".to_string()
+    } else {
+        v
+    }
+}
+
+/// RFC 5424 TIMESTAMP: UTC with at most six fractional digits, which the
+/// ledger's nanosecond RFC 3339 timestamps exceed.
+fn syslog_timestamp(ts: Option<&str>) -> String {
+    let Some(t) = ts.and_then(|ts| OffsetDateTime::parse(ts, &Rfc3339).ok()) else {
+        return "-".to_string();
+    };
+    let t = t.to_offset(UtcOffset::UTC);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        t.year(),
+        t.month() as u8,
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second(),
+        t.microsecond()
+    )
+}
+
+/// One RFC 5424 syslog message; the JSON payload is the MSG part.
+pub fn syslog(ev: &AuditEvent, seq: u64) -> String {
+    let m = mapping(ev);
+    let pri = FACILITY_LOG_AUDIT * 8 + m.severity.syslog();
+    let mut sd = format!(
+        "[{} seq=\"{}\" request_id=\"{}\" action=\"{}\" hash=\"{}\"",
+        SD_ID,
+        seq,
+        sd_param(&ev.request_id),
+        m.action,
+        ev.hash
+    );
+    if let Some(id) = &ev.http_request_id {
+        sd.push_str(&format!(" http_request_id=\"{}\"", sd_param(id)));
+    }
+    sd.push(']');
+    format!(
+        "<{}>1 {} {} {} - {} {} {}",
+        pri,
+        syslog_timestamp(ev.ts.as_deref()),
+        header_field(ev.instance_id.as_deref(), 255),
+        APP_NAME,
+        header_field(Some(&ev.event_type), 32),
+        sd,
+        ev.payload
+    )
+}
+
+pub fn render(format: Format, ev: &AuditEvent, seq: u64) -> String {
+    match format {
+        Format::Jsonl => serde_json::to_string(ev).unwrap_or_default(),
+        Format::Ocsf => ocsf(ev, seq).to_string(),
+        Format::Cef => cef(ev, seq),
+        Format::Syslog => syslog(ev, seq),
+    }
+}
+
+/// Converts ledger JSONL into `format` line by line as it is read. Raw mode
+/// passes lines through untouched; otherwise lines that do not parse are skipped.
+pub struct SiemReader<R> {
+    lines: R,
+    format: Format,
+    seq: u64,
+    scrub: Box<dyn Fn(&str) -> String + Send>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> SiemReader<R> {
+    /// `start_seq` is the position of the record before the first one read.
+    pub fn new(
+        lines: R,
+        format: Format,
+        start_seq: u64,
+        scrub: Box<dyn Fn(&str) -> String + Send>,
+    ) -> Self {
+        Self {
+            lines,
+            format,
+            seq: start_seq,
+            scrub,
+            buf: vec![],
+            pos: 0,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            let mut line = String::new();
+            if self.lines.read_line(&mut line)? == 0 {
+                return Ok(false);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.seq += 1;
+            let line = (self.scrub)(line.trim_end());
+            let out = match serde_json::from_str::<AuditEvent>(&line) {
+                _ if self.format == Format::Jsonl => line,
+                Ok(ev) => render(self.format, &ev, ev.seq.unwrap_or(self.seq)),
+                Err(_) => continue,
+            };
+            self.buf = out.into_bytes();
+            self.buf.push(b'\n');
+            self.pos = 0;
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: BufRead> Read for SiemReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.buf.len() && !self.fill()? {
+            return Ok(0);
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(ts: &str) -> AuditEvent {
+        AuditEvent {
+            seq: Some(7),
+            ts: Some(ts.to_string()),
+            instance_id: Some("vm-1".to_string()),
+            http_request_id: Some("req \"1\"".to_string()),
+            event_type: "prompt.deny".to_string(),
+            request_id: "3f1c".to_string(),
+            payload: serde_json::json!({"reason": "secrets_detected"}),
+            prev_hash: "GENESIS".to_string(),
+            hash: "ab".repeat(32),
+        }
+    }
+
+    #[test]
+    fn syslog_header_matches_rfc5424() {
+        // PRI VERSION SP TIMESTAMP SP HOSTNAME SP APP-NAME SP PROCID SP MSGID SP SD
+        let header = regex::Regex::new(concat!(
+            r"^<(?:[0-9]|[1-9][0-9]|1[0-8][0-9]|19[01])>1 ",
+            r"(?:-|[0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9]{2}:[0-9]{2}:[0-9]{2}(?:\.[0-9]{1,6})?(?:Z|[+-][0-9]{2}:[0-9]{2})) ",
+            r"[!-~]{1,255} [!-~]{1,48} [!-~]{1,128} [!-~]{1,32} ",
+            r"\[[!-~&&[^= \]\x22]]{1,32}(?: [!-~&&[^= \]\x22]]{1,32}=\x22(?:[^\x22\\\]]|\\.)*\x22)*\] ",
+        ))
+        .unwrap();
+        for ts in [
+            "2026-10-17T08:57:02.358531453Z",
+            "2026-10-17T10:57:02+02:00",
+            "not a timestamp",
+        ] {
+            let line = syslog(&event(ts), 7);
+            assert!(header.is_match(&line), "{}", line);
+        }
+        let line = syslog(&event("2026-10-17T10:57:02.358531453+02:00"), 7);
+        assert!(line.contains(" 2026-10-17T08:57:02.358531Z "), "{}", line);
+    }
+}
@@ -0,0 +1,202 @@
+use ed25519_dalek::VerifyingKey;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use super::{
+    checkpoint,
+    segment::{self, Manifest, SegmentReader},
+    AuditEvent, LEDGER_REQUEST_ID,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BrokenLink {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainGap {
+    pub line: u64,
+    /// true when the restart was announced with an `audit.segment.start` record
+    pub announced: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateId {
+    pub request_id: String,
+    pub event_type: String,
+    pub lines: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub ok: bool,
+    pub records: u64,
+    pub head_hash: String,
+    pub first_broken: Option<BrokenLink>,
+    pub gaps: Vec<ChainGap>,
+    pub duplicate_request_ids: Vec<DuplicateId>,
+    pub checkpoints: u64,
+    /// false when no verifying key was available and signatures were not checked
+    pub signatures_verified: bool,
+    pub last_checkpoint_seq: Option<u64>,
+    pub records_after_last_checkpoint: u64,
+}
+
+impl VerifyReport {
+    fn mark_broken(&mut self, line: u64, reason: String) {
+        if self.first_broken.is_none() {
+            self.first_broken = Some(BrokenLink { line, reason });
+        }
+    }
+}
+
+/// Recomputes every record hash exactly as `append` does, walks the
+/// `prev_hash` links and checks each record's `seq` against its position.
+/// A restart at GENESIS is reported as a gap and, unless announced or in a
+/// legacy record without `seq`, also fails the report; a bad hash, a broken link, a
+/// skipped or repeated seq, a bad checkpoint or an unreadable line makes the
+/// report fail. With a verifying key, so does a ledger with no checkpoint or
+/// more than `checkpoint_every` records after the last one.
+/// `start` is the hash and position the first record continues from.
+pub fn verify_chain<R: BufRead>(
+    reader: R,
+    start: (String, u64),
+    vk: Option<&VerifyingKey>,
+    checkpoint_every: u64,
+) -> VerifyReport {
+    let (start_hash, mut seq) = start;
+    let mut report = VerifyReport {
+        ok: true,
+        records: 0,
+        head_hash: start_hash,
+        first_broken: None,
+        gaps: vec![],
+        duplicate_request_ids: vec![],
+        checkpoints: 0,
+        signatures_verified: vk.is_some(),
+        last_checkpoint_seq: None,
+        records_after_last_checkpoint: 0,
+    };
+    let mut seen: HashMap<(String, String), Vec<u64>> = HashMap::new();
+    let mut last_line = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let n = i as u64 + 1;
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                report.mark_broken(n, format!("read error: {}", e));
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        seq += 1;
+        let ev = match serde_json::from_str::<AuditEvent>(&line) {
+            Ok(ev) => ev,
+            Err(e) => {
+                report.mark_broken(n, format!("unparseable record: {}", e));
+                continue;
+            }
+        };
+        report.records += 1;
+        last_line = n;
+        if !ev.hash_ok() {
+            report.mark_broken(n, "hash mismatch".to_string());
+        }
+        if let Some(ev_seq) = ev.seq {
+            if ev_seq != seq {
+                let reason = format!("seq {} where {} was expected", ev_seq, seq);
+                report.mark_broken(n, reason);
+            }
+        }
+        if ev.prev_hash == "GENESIS" {
+            if report.records > 1 {
+                let announced = ev.event_type == "audit.segment.start";
+                report.gaps.push(ChainGap { line: n, announced });
+                // records from before `seq` existed may restart silently; newer ones
+                // only restart through the writer, which always announces it
+                if !announced && ev.seq.is_some() {
+                    report.mark_broken(n, "unannounced restart at GENESIS".to_string());
+                }
+            }
+        } else if ev.prev_hash != report.head_hash {
+            let reason = format!(
+                "prev_hash {} does not match previous record {}",
+                ev.prev_hash, report.head_hash
+            );
+            report.mark_broken(n, reason);
+        }
+        if ev.event_type == checkpoint::CHECKPOINT_EVENT {
+            match checkpoint::check(&ev.payload, seq - 1, &report.head_hash, vk) {
+                Ok(()) => {
+                    report.checkpoints += 1;
+                    report.last_checkpoint_seq = Some(seq - 1);
+                    report.records_after_last_checkpoint = 0;
+                }
+                Err(reason) => report.mark_broken(n, reason),
+            }
+        } else {
+            report.records_after_last_checkpoint += 1;
+        }
+        if ev.request_id != LEDGER_REQUEST_ID {
+            seen.entry((ev.request_id.clone(), ev.event_type.clone()))
+                .or_default()
+                .push(n);
+        }
+        report.head_hash = ev.hash;
+    }
+    let mut dups: Vec<DuplicateId> = seen
+        .into_iter()
+        .filter(|(_, lines)| lines.len() > 1)
+        .map(|((request_id, event_type), lines)| DuplicateId {
+            request_id,
+            event_type,
+            lines,
+        })
+        .collect();
+    dups.sort_by_key(|d| d.lines[0]);
+    report.duplicate_request_ids = dups;
+    if vk.is_some() && report.records > 0 {
+        // an unsigned tail could have been rewritten along with its hashes
+        if report.checkpoints == 0 {
+            report.mark_broken(last_line, "no signed checkpoint".to_string());
+        } else if report.records_after_last_checkpoint > checkpoint_every {
+            let reason = format!(
+                "{} records after the last checkpoint, more than the interval of {}",
+                report.records_after_last_checkpoint, checkpoint_every
+            );
+            report.mark_broken(last_line, reason);
+        }
+    }
+    report.ok = report.first_broken.is_none();
+    report
+}
+
+/// Verifies the ledger whose active segment is `path`, following the segment
+/// manifest next to it when there is one.
+pub fn verify_file(
+    path: &Path,
+    vk: Option<&VerifyingKey>,
+    checkpoint_every: u64,
+) -> std::io::Result<VerifyReport> {
+    File::open(path)?;
+    let manifest = Manifest::load(&segment::manifest_path(path))?;
+    let start = manifest
+        .as_ref()
+        .map(|m| m.start())
+        .unwrap_or_else(|| ("GENESIS".to_string(), 0));
+    let reader = SegmentReader::new(segment::chain_files(path, manifest.as_ref()));
+    Ok(verify_chain(
+        BufReader::new(reader),
+        start,
+        vk,
+        checkpoint_every,
+    ))
+}
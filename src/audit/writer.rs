@@ -0,0 +1,358 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::metrics;
+
+use super::{
+    checkpoint, now_unix,
+    segment::{self, ActiveSegment, Manifest, SegmentInfo},
+    AuditEvent, AuditIndex, CheckpointSigner, Forwarder, IndexEntry, MerkleLog, RotationPolicy,
+    LEDGER_REQUEST_ID,
+};
+
+/// When appended records are forced to disk.
+#[derive(Debug, Clone, Copy)]
+pub enum FsyncPolicy {
+    /// after every record, before it is acknowledged
+    Every,
+    /// once per group of records drained from the queue together
+    Batch,
+    /// on a timer; records are acknowledged once written, not yet synced
+    Interval(Duration),
+}
+
+impl FsyncPolicy {
+    pub fn parse(mode: &str, interval_ms: u64) -> Result<Self, String> {
+        match mode {
+            "every" => Ok(Self::Every),
+            "batch" => Ok(Self::Batch),
+            "interval" => Ok(Self::Interval(Duration::from_millis(interval_ms.max(1)))),
+            other => Err(format!("unknown fsync policy {}", other)),
+        }
+    }
+}
+
+/// Most records written between two looks at the queue; also the group size
+/// of a batch fsync.
+const MAX_BATCH: usize = 256;
+
+pub(super) type Ack = oneshot::Sender<Result<(), String>>;
+
+pub(super) enum Job {
+    Append {
+        event_type: String,
+        request_id: String,
+        http_request_id: Option<String>,
+        payload: serde_json::Value,
+        done: Option<Ack>,
+    },
+    /// fsync now if anything was written since the last one
+    Sync { done: Option<Ack> },
+}
+
+pub(super) struct Head {
+    pub hash: String,
+    /// number of records written so far, i.e. the position of `hash`
+    pub seq: u64,
+    pub active_opened_at: i64,
+}
+
+/// Queues a sync every `every` for as long as the ledger exists.
+pub(super) fn spawn_ticker(tx: mpsc::WeakSender<Job>, every: Duration) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(every);
+        let Some(tx) = tx.upgrade() else {
+            break;
+        };
+        if tx.blocking_send(Job::Sync { done: None }).is_err() {
+            break;
+        }
+    });
+}
+
+/// Owns the active segment and the chain head. Runs on its own thread and is
+/// the only thing that ever writes to the ledger.
+pub(super) struct Writer {
+    pub path: PathBuf,
+    pub manifest_path: PathBuf,
+    pub manifest: Arc<Mutex<Manifest>>,
+    pub index: Arc<Mutex<AuditIndex>>,
+    pub merkle: Arc<Mutex<MerkleLog>>,
+    pub rotation: RotationPolicy,
+    pub signer: Option<Arc<CheckpointSigner>>,
+    pub instance_id: String,
+    pub forwarder: Option<Forwarder>,
+    pub fsync: FsyncPolicy,
+    pub head: Head,
+    pub file: Option<File>,
+    /// written since the last fsync
+    pub dirty: bool,
+    /// a scheduled checkpoint failed to write and is retried on the next append
+    pub checkpoint_due: bool,
+}
+
+impl Writer {
+    fn file(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            let f = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.file = Some(f);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.file()?.sync_data()?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn write_record(
+        &mut self,
+        event_type: &str,
+        request_id: &str,
+        http_request_id: Option<String>,
+        payload: serde_json::Value,
+    ) -> io::Result<()> {
+        let mut ev = AuditEvent {
+            seq: Some(self.head.seq + 1),
+            ts: OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+            instance_id: Some(self.instance_id.clone()),
+            http_request_id,
+            event_type: event_type.to_string(),
+            request_id: request_id.to_string(),
+            payload,
+            prev_hash: self.head.hash.clone(),
+            hash: String::new(),
+        };
+        ev.hash = ev.compute_hash();
+        let line = format!("{}\n", serde_json::to_string(&ev).unwrap_or_default());
+        let f = self.file()?;
+        let offset = f.metadata()?.len();
+        if let Err(e) = f.write_all(line.as_bytes()) {
+            // drop a partial line so the next record does not continue it
+            let _ = f.set_len(offset);
+            self.file = None;
+            return Err(e);
+        }
+        self.head.hash = ev.hash.clone();
+        self.head.seq += 1;
+        self.dirty = true;
+        let entry = IndexEntry::new(self.head.seq, offset, line.len() as u64, &ev);
+        let index = self.index.lock().unwrap().record(entry);
+        let merkle = self.merkle.lock().unwrap().record(&ev.hash);
+        if let Some(fw) = &self.forwarder {
+            fw.forward(&ev);
+        }
+        if let FsyncPolicy::Every = self.fsync {
+            self.sync()?;
+        }
+        // the record is in the ledger either way; its sidecars catch up on the next write
+        index.map_err(|e| io::Error::new(e.kind(), format!("audit index: {}", e)))?;
+        merkle.map_err(|e| io::Error::new(e.kind(), format!("audit merkle leaves: {}", e)))
+    }
+
+    pub fn write_checkpoint(&mut self) -> io::Result<()> {
+        if let Some(signer) = self.signer.clone() {
+            let cp = signer.checkpoint(self.head.seq, &self.head.hash);
+            self.write_record(checkpoint::CHECKPOINT_EVENT, LEDGER_REQUEST_ID, None, cp)?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self) -> bool {
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size == 0 {
+            return false;
+        }
+        (self.rotation.max_bytes > 0 && size >= self.rotation.max_bytes)
+            || (self.rotation.max_age_secs > 0
+                && now_unix() - self.head.active_opened_at >= self.rotation.max_age_secs)
+    }
+
+    /// Closes the active segment and opens a new one whose first record links to
+    /// the closed segment's final hash. Compression and retention run off-thread.
+    fn rotate(&mut self) -> io::Result<()> {
+        let now = now_unix();
+        // the closed segment is final: make sure all of it is on disk
+        self.sync()?;
+        self.file = None;
+        let file = {
+            let mut m = self.manifest.lock().unwrap();
+            let file = segment::closed_name(&self.path, m.next_index);
+            let mut next = m.clone();
+            next.next_index += 1;
+            next.segments.push(SegmentInfo {
+                file: file.clone(),
+                first_seq: m.active.first_seq,
+                last_seq: self.head.seq,
+                first_prev_hash: m.active.first_prev_hash.clone(),
+                last_hash: self.head.hash.clone(),
+                opened_at: m.active.opened_at,
+                closed_at: now,
+                compressed: false,
+            });
+            next.active = ActiveSegment {
+                first_seq: self.head.seq + 1,
+                first_prev_hash: self.head.hash.clone(),
+                opened_at: now,
+            };
+            // the manifest goes first: a crash before the rename is completed by
+            // `finish_rotation` on open. A segment that cannot be closed fails the
+            // append rather than growing the active file past the rotation policy
+            next.save(&self.manifest_path)?;
+            if let Err(e) = fs::rename(&self.path, self.path.with_file_name(&file)) {
+                let _ = m.save(&self.manifest_path);
+                return Err(e);
+            }
+            *m = next;
+            file
+        };
+        self.head.active_opened_at = now;
+        let previous_last_hash = self.head.hash.clone();
+        self.write_record(
+            "audit.segment.start",
+            LEDGER_REQUEST_ID,
+            None,
+            serde_json::json!({
+                "reason": "rotation",
+                "previous_segment": file,
+                "previous_last_hash": previous_last_hash,
+            }),
+        )?;
+        self.write_checkpoint()?;
+        self.housekeeping(file);
+        Ok(())
+    }
+
+    fn housekeeping(&self, file: String) {
+        let manifest = self.manifest.clone();
+        let manifest_path = self.manifest_path.clone();
+        let active = self.path.clone();
+        let policy = self.rotation.clone();
+        std::thread::spawn(move || {
+            let compressed = if policy.compress {
+                segment::compress(&active.with_file_name(&file)).ok()
+            } else {
+                None
+            };
+            let mut m = manifest.lock().unwrap();
+            if let Some(gz) = compressed {
+                if let Some(seg) = m.segments.iter_mut().find(|s| s.file == file) {
+                    seg.file = gz
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or(file);
+                    seg.compressed = true;
+                }
+            }
+            let pruned = segment::prune(&mut m, &policy, now_unix());
+            // pruned files stay until a manifest without them is on disk
+            match m.save(&manifest_path) {
+                Ok(()) => segment::remove_pruned(&active, pruned),
+                Err(e) => tracing::warn!(error = %e, "audit manifest save failed; segments kept"),
+            }
+        });
+    }
+
+    fn append(
+        &mut self,
+        event_type: &str,
+        request_id: &str,
+        http_request_id: Option<String>,
+        payload: serde_json::Value,
+    ) -> io::Result<()> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+        let before = self.head.seq;
+        let written = self.write_record(event_type, request_id, http_request_id, payload);
+        if let Some(signer) = &self.signer {
+            // also when only a sidecar failed: the record itself counts
+            self.checkpoint_due |=
+                self.head.seq > before && self.head.seq.is_multiple_of(signer.every);
+        }
+        written?;
+        // the record itself is on the ledger; a failed checkpoint must not be
+        // reported as a failed append
+        if self.checkpoint_due {
+            match self.write_checkpoint() {
+                Ok(()) => self.checkpoint_due = false,
+                Err(e) => {
+                    metrics::AUDIT_WRITE_FAILURES.inc();
+                    tracing::error!(error = %e, seq = self.head.seq, "audit checkpoint failed");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drains the queue until every sender is gone. Whatever is waiting when
+    /// the thread wakes up is written as one group before anyone is acknowledged.
+    pub fn run(mut self, mut rx: mpsc::Receiver<Job>) {
+        while let Some(first) = rx.blocking_recv() {
+            let mut jobs = vec![first];
+            while jobs.len() < MAX_BATCH {
+                match rx.try_recv() {
+                    Ok(job) => jobs.push(job),
+                    Err(_) => break,
+                }
+            }
+            let mut acks: Vec<(Option<Ack>, Result<(), String>)> = Vec::with_capacity(jobs.len());
+            for job in jobs {
+                match job {
+                    Job::Append {
+                        event_type,
+                        request_id,
+                        http_request_id,
+                        payload,
+                        done,
+                    } => {
+                        let r = self.append(&event_type, &request_id, http_request_id, payload);
+                        acks.push((done, r.map_err(|e| format!("audit write: {}", e))));
+                    }
+                    Job::Sync { done } => {
+                        let r = self.sync().map_err(|e| format!("audit fsync: {}", e));
+                        acks.push((done, r));
+                    }
+                }
+            }
+            if let FsyncPolicy::Batch = self.fsync {
+                if let Err(e) = self.sync() {
+                    let e = format!("audit fsync: {}", e);
+                    for (_, r) in acks.iter_mut().filter(|(_, r)| r.is_ok()) {
+                        *r = Err(e.clone());
+                    }
+                }
+            }
+            for (done, r) in acks {
+                if r.is_err() {
+                    metrics::AUDIT_WRITE_FAILURES.inc();
+                }
+                match done {
+                    Some(done) => {
+                        let _ = done.send(r);
+                    }
+                    None => {
+                        if let Err(e) = r {
+                            tracing::error!(error = %e, "audit write failed");
+                        }
+                    }
+                }
+            }
+        }
+        let _ = self.sync();
+    }
+}
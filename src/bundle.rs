@@ -5,7 +5,7 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use bytes::Bytes;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
 use zip::{
     write::{ExtendedFileOptions, FileOptions},
     ZipWriter,
@@ -22,14 +22,19 @@ pub async fn get_bundle(State(st): State<AppState>, Path(request_id): Path<Strin
                 .unwrap_or_default()
                 .as_bytes(),
         );
-        let audit = st.ledger.export_all();
         let _ = zip.start_file("audit_slice.jsonl", opts);
+        let dlp = st.dlp.engine();
         let mut slice = String::new();
-        for line in audit.lines() {
-            if line.contains(&request_id) {
-                slice.push_str(line);
-                slice.push('\n');
+        for line in BufReader::new(st.ledger.reader())
+            .lines()
+            .map_while(Result::ok)
+        {
+            if !line.contains(&request_id) {
+                continue;
             }
+            // older records carry raw DLP matches; those must not leave in a bundle
+            slice.push_str(&dlp.scrub_line(&line));
+            slice.push('\n');
         }
         let _ = zip.write_all(slice.as_bytes());
         let _ = zip.finish();
@@ -0,0 +1,197 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{approvals, audit, config::Policy, dlp::DlpEngine};
+
+/// Runs a maintenance subcommand if one was given on the command line and
+/// returns its exit code; `None` means start the gateway as usual.
+pub fn run(args: &[String]) -> Option<i32> {
+    match args.get(1).map(String::as_str) {
+        Some("verify-audit") => Some(verify_audit(&args[2..])),
+        Some("bench-dlp") => Some(bench_dlp(&args[2..])),
+        _ => None,
+    }
+}
+
+const VERIFY_USAGE: &str =
+    "usage: aegis verify-audit <file> [--verifying-key <b64>] [--checkpoint-every <n>]";
+
+/// `aegis verify-audit <file> [--verifying-key <b64>] [--checkpoint-every <n>]`:
+/// prints the report as JSON, exits 1 when the chain was tampered with and 2
+/// on usage or read errors.
+/// The key falls back to `AEGIS_AUDIT_VK_B64` and the interval to
+/// `AEGIS_AUDIT_CHECKPOINT_EVERY` (100); without a key, checkpoint signatures
+/// are not checked.
+fn verify_audit(args: &[String]) -> i32 {
+    let mut path = None;
+    let mut vk_b64 = std::env::var("AEGIS_AUDIT_VK_B64").ok();
+    let mut every = std::env::var("AEGIS_AUDIT_CHECKPOINT_EVERY").ok();
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "--verifying-key" => vk_b64 = it.next().cloned(),
+            "--checkpoint-every" => every = it.next().cloned(),
+            _ if path.is_none() => path = Some(a.clone()),
+            _ => {
+                eprintln!("{}", VERIFY_USAGE);
+                return 2;
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{}", VERIFY_USAGE);
+        return 2;
+    };
+    let vk = match vk_b64.filter(|s| !s.trim().is_empty()) {
+        Some(b64) => match approvals::verifying_key_from_b64(&b64) {
+            Some(vk) => Some(vk),
+            None => {
+                eprintln!("verify-audit: bad verifying key");
+                return 2;
+            }
+        },
+        None => None,
+    };
+    let every = match every.as_deref().map(str::parse::<u64>) {
+        None => 100,
+        Some(Ok(n)) => n.max(1),
+        Some(Err(_)) => {
+            eprintln!("verify-audit: bad checkpoint interval");
+            return 2;
+        }
+    };
+    match audit::verify_file(Path::new(&path), vk.as_ref(), every) {
+        Ok(report) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).unwrap_or_default()
+            );
+            if report.ok {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("verify-audit: {}: {}", path, e);
+            2
+        }
+    }
+}
+
+const BENCH_USAGE: &str =
+    "usage: aegis bench-dlp [--packs <dir>] [--policy <file>] [--sizes <kb,kb,...>] [--secs <n>]";
+
+/// Filler for benchmark prompts: ordinary prose that gets near several rules
+/// without matching them, so every size pays for a full scan.
+const BENCH_TEXT: &str = "Please review the quarterly report and summarise the system \
+changes. The policy team wants to know which instructions were updated, how the \
+developer tooling behaves, and whether any keys or tokens rotated last month. ";
+
+/// `aegis bench-dlp [--packs <dir>] [--policy <file>] [--sizes <kb,...>] [--secs <n>]`:
+/// scans chat requests of each size (KiB, default 1,16,256,1024) the way the
+/// gateway does, with the packs in `<dir>` (default `policy/packs`) and the
+/// finding kinds `<file>` enables (default `policy/packs/policy.json`), for
+/// about `<n>` seconds each (default 1), and prints one JSON line per size
+/// with the scan latency per KiB.
+fn bench_dlp(args: &[String]) -> i32 {
+    let mut dir = "policy/packs".to_string();
+    let mut policy_path = "policy/packs/policy.json".to_string();
+    let mut sizes = vec![1usize, 16, 256, 1024];
+    let mut secs = 1.0f64;
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        let ok = match (a.as_str(), it.next()) {
+            ("--packs", Some(v)) => {
+                dir = v.clone();
+                true
+            }
+            ("--policy", Some(v)) => {
+                policy_path = v.clone();
+                true
+            }
+            ("--sizes", Some(v)) => match v.split(',').map(|s| s.trim().parse()).collect() {
+                Ok(v) => {
+                    sizes = v;
+                    true
+                }
+                Err(_) => false,
+            },
+            ("--secs", Some(v)) => match v.parse::<f64>() {
+                Ok(v) if v.is_finite() && v > 0.0 => {
+                    secs = v;
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if !ok {
+            eprintln!("{}", BENCH_USAGE);
+            return 2;
+        }
+    }
+    let policy: Policy = match std::fs::read(&policy_path)
+        .map_err(|e| e.to_string())
+        .and_then(|b| serde_json::from_slice(&b).map_err(|e| e.to_string()))
+    {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("bench-dlp: {}: {}", policy_path, e);
+            return 2;
+        }
+    };
+    let started = Instant::now();
+    let engine = match DlpEngine::load(Path::new(&dir), b"bench-dlp") {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("bench-dlp: {}", e);
+            return 2;
+        }
+    };
+    println!(
+        "{}",
+        serde_json::json!({
+            "packs": engine.packs(),
+            "rules": engine.rule_count(),
+            "compile_ms": started.elapsed().as_secs_f64() * 1e3,
+        })
+    );
+    for kb in sizes {
+        let mut content = String::with_capacity(kb * 1024 + BENCH_TEXT.len());
+        while content.len() < kb * 1024 {
+            content.push_str(BENCH_TEXT);
+        }
+        content.truncate(kb * 1024);
+        let body = serde_json::json!({
+            "model": "bench",
+            "messages": [{"role": "user", "content": content}],
+        });
+        let body_bytes = body.to_string().len();
+        let budget = Duration::from_secs_f64(secs);
+        let started = Instant::now();
+        let mut iterations = 0u64;
+        let mut findings = 0;
+        while iterations == 0 || started.elapsed() < budget {
+            findings = engine.scan_json(&body, &policy).len();
+            iterations += 1;
+        }
+        let per_scan = started.elapsed().as_secs_f64() / iterations as f64;
+        let kib = body_bytes as f64 / 1024.0;
+        println!(
+            "{}",
+            serde_json::json!({
+                "kb": kb,
+                "body_bytes": body_bytes,
+                "iterations": iterations,
+                "findings": findings,
+                "scan_us": per_scan * 1e6,
+                "us_per_kb": per_scan * 1e6 / kib,
+                "mb_per_s": kib / 1024.0 / per_scan,
+            })
+        );
+    }
+    0
+}
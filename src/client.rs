@@ -0,0 +1,90 @@
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+/// Who sent a request, as far as the gateway can tell. Inserted into request
+/// extensions by the client middleware.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub principal: Option<String>,
+}
+
+/// Set by the auth middleware once a bearer token has been accepted.
+#[derive(Debug, Clone)]
+pub struct Principal(pub String);
+
+/// Stable, non-reversible name for a bearer token.
+pub fn token_principal(token: &str) -> String {
+    format!(
+        "token:{}",
+        &hex::encode(Sha256::digest(token.as_bytes()))[..12]
+    )
+}
+
+/// Peers allowed to speak for the client through `X-Forwarded-For` and
+/// `X-Forwarded-User`: `AEGIS_TRUSTED_PROXIES`, comma separated IPs or CIDRs.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut nets = vec![];
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let net = match item.parse::<IpNet>() {
+                Ok(n) => n,
+                Err(_) => item
+                    .parse::<IpAddr>()
+                    .map(IpNet::from)
+                    .map_err(|_| format!("trusted proxy {}: not an IP or CIDR", item))?,
+            };
+            nets.push(net);
+        }
+        Ok(Self(nets))
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|n| n.contains(&ip))
+    }
+
+    /// The peer, unless it is a trusted proxy; then the right-most
+    /// `X-Forwarded-For` hop that is not itself a trusted proxy. Hops left of
+    /// that were supplied by the client and are ignored.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        let hops: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .map(|h| h.parse::<IpAddr>())
+            .collect::<Result<_, _>>()
+            .unwrap_or_default();
+        let mut client = peer;
+        for hop in hops.into_iter().rev() {
+            client = hop;
+            if !self.contains(hop) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// Principal asserted by a trusted proxy that did the authentication.
+    pub fn forwarded_user(&self, peer: IpAddr, headers: &HeaderMap) -> Option<String> {
+        if !self.contains(peer) {
+            return None;
+        }
+        headers
+            .get("x-forwarded-user")
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .map(str::to_string)
+    }
+}
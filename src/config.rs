@@ -1,14 +1,24 @@
 use crate::{
-    audit::AuditLedger, gateway::UpstreamClient, opa::OpaClient, tools::registry::ToolRegistry,
+    alerts::Alerter,
+    approvals,
+    audit::{
+        forward, siem, AuditLedger, CheckpointSigner, CorruptTail, Forwarder, FsyncPolicy,
+        LedgerOptions, RotationPolicy,
+    },
+    client::TrustedProxies,
+    dlp::DlpRules,
+    gateway::UpstreamClient,
+    opa::OpaClient,
+    threats::{ThreatBus, ThreatStore},
+    tools::registry::ToolRegistry,
+    vault::TokenVault,
 };
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::{HashSet, VecDeque},
-    fs,
-    net::SocketAddr,
-    path::PathBuf,
-    sync::Arc,
+use chacha20poly1305::{
+    aead::{KeyInit, OsRng},
+    ChaCha20Poly1305,
 };
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, net::SocketAddr, path::PathBuf, sync::Arc};
 use time::OffsetDateTime;
 use tokio::sync::RwLock;
 
@@ -32,6 +42,8 @@ pub struct Policy {
     pub fail_closed: bool,
     pub redact_before_upstream: bool,
     pub redact_response_to_client: bool,
+    #[serde(default)]
+    pub tokenize_redactions: bool,
     pub allowed_domains: HashSet<String>,
     pub block_unknown_domains: bool,
     pub block_on_secrets: bool,
@@ -48,6 +60,7 @@ pub struct Policy {
 pub struct AppState {
     pub policy: Arc<Policy>,
     pub policy_raw: Arc<Vec<u8>>,
+    pub dlp: Arc<DlpRules>,
     pub ledger: Arc<AuditLedger>,
     pub opa: Option<Arc<OpaClient>>,
     pub opa_path: String,
@@ -57,33 +70,136 @@ pub struct AppState {
     #[allow(dead_code)]
     pub sandbox_timeout_ms: u64,
     pub started_at: OffsetDateTime,
-    pub threats: Arc<RwLock<VecDeque<crate::gateway::Threat>>>,
+    pub threats: Arc<ThreatBus>,
     pub auth_token: Option<String>,
+    pub vault: Arc<TokenVault>,
+}
+
+/// `<hostname>-<random>`, so restarts and replicas on one host stay distinguishable.
+fn default_instance_id() -> String {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "aegis".to_string());
+    format!(
+        "{}-{}",
+        host,
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    )
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     policy_path: PathBuf,
+    dlp_packs_dir: PathBuf,
+    finding_hmac_key: Option<String>,
     bind: SocketAddr,
     audit_path: PathBuf,
+    audit_on_corrupt: CorruptTail,
+    audit_sk_b64: Option<String>,
+    audit_vk_b64: Option<String>,
+    audit_checkpoint_every: u64,
+    audit_rotation: RotationPolicy,
+    audit_fsync: FsyncPolicy,
+    audit_queue: usize,
+    audit_fail_closed: bool,
+    instance_id: String,
+    syslog_target: Option<forward::Target>,
+    syslog_format: siem::Format,
     artifacts_dir: PathBuf,
     upstream_override: Option<String>,
     opa_url: Option<String>,
     opa_path: String,
     sandbox_timeout_ms: u64,
     auth_token: Option<String>,
+    trusted_proxies: TrustedProxies,
+    threats_path: PathBuf,
+    threats_retain_days: i64,
+    alerts_path: Option<PathBuf>,
+    otlp_endpoint: Option<String>,
+    log_format: String,
 }
 
 impl Config {
     pub fn load() -> Result<Self, String> {
         let policy_path = std::env::var("AEGIS_POLICY_PATH")
             .unwrap_or_else(|_| "policy/packs/policy.json".to_string());
+        // rule packs live next to the policy unless pointed elsewhere
+        let dlp_packs_dir = std::env::var("AEGIS_DLP_PACKS_DIR")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                std::path::Path::new(&policy_path)
+                    .parent()
+                    .map(|p| p.to_path_buf())
+                    .unwrap_or_default()
+            });
+        let finding_hmac_key = std::env::var("AEGIS_FINDING_HMAC_KEY")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
         let bind_s = std::env::var("AEGIS_BIND").unwrap_or_else(|_| "127.0.0.1:8088".to_string());
         let bind: SocketAddr = bind_s
             .parse()
             .map_err(|e: std::net::AddrParseError| e.to_string())?;
         let audit_path =
             std::env::var("AEGIS_AUDIT_PATH").unwrap_or_else(|_| "aegis_audit.jsonl".to_string());
+        let audit_on_corrupt = match std::env::var("AEGIS_AUDIT_ON_CORRUPT")
+            .unwrap_or_else(|_| "refuse".to_string())
+            .as_str()
+        {
+            "refuse" => CorruptTail::Refuse,
+            "new_segment" => CorruptTail::NewSegment,
+            other => return Err(format!("AEGIS_AUDIT_ON_CORRUPT: unknown mode {}", other)),
+        };
+        let audit_sk_b64 = std::env::var("AEGIS_AUDIT_SK_B64")
+            .or_else(|_| std::env::var("AEGIS_OPERATOR_SK_B64"))
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        let audit_vk_b64 = std::env::var("AEGIS_AUDIT_VK_B64")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        let audit_checkpoint_every = std::env::var("AEGIS_AUDIT_CHECKPOINT_EVERY")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(100);
+        let env_num = |k: &str, default: i64| {
+            std::env::var(k)
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(default)
+                .max(0)
+        };
+        let audit_rotation = RotationPolicy {
+            max_bytes: env_num("AEGIS_AUDIT_MAX_SEGMENT_BYTES", 64 * 1024 * 1024) as u64,
+            max_age_secs: env_num("AEGIS_AUDIT_MAX_SEGMENT_AGE_SECS", 0),
+            retain_segments: env_num("AEGIS_AUDIT_RETAIN_SEGMENTS", 0) as usize,
+            retain_days: env_num("AEGIS_AUDIT_RETAIN_DAYS", 0),
+            compress: std::env::var("AEGIS_AUDIT_COMPRESS").unwrap_or_else(|_| "1".to_string())
+                == "1",
+        };
+        let audit_fsync = FsyncPolicy::parse(
+            &std::env::var("AEGIS_AUDIT_FSYNC").unwrap_or_else(|_| "batch".to_string()),
+            env_num("AEGIS_AUDIT_FSYNC_INTERVAL_MS", 1000) as u64,
+        )
+        .map_err(|e| format!("AEGIS_AUDIT_FSYNC: {}", e))?;
+        let audit_queue = env_num("AEGIS_AUDIT_QUEUE", 1024) as usize;
+        let audit_fail_closed =
+            std::env::var("AEGIS_AUDIT_FAIL_CLOSED").unwrap_or_else(|_| "0".to_string()) == "1";
+        let instance_id = std::env::var("AEGIS_INSTANCE_ID")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(default_instance_id);
+        let syslog_target = match std::env::var("AEGIS_SYSLOG_TARGET") {
+            Ok(t) if !t.trim().is_empty() => Some(forward::Target::parse(t.trim())?),
+            _ => None,
+        };
+        let syslog_format = siem::Format::parse(
+            &std::env::var("AEGIS_SYSLOG_FORMAT").unwrap_or_else(|_| "syslog".to_string()),
+        )
+        .map_err(|e| format!("AEGIS_SYSLOG_FORMAT: {}", e))?;
         let artifacts_dir =
             std::env::var("AEGIS_ARTIFACTS_DIR").unwrap_or_else(|_| "artifacts".to_string());
         let upstream_override = std::env::var("AEGIS_UPSTREAM").ok();
@@ -97,21 +213,69 @@ impl Config {
         let auth_token = std::env::var("AEGIS_UI_TOKEN")
             .ok()
             .filter(|s| !s.trim().is_empty());
+        let trusted_proxies =
+            TrustedProxies::parse(&std::env::var("AEGIS_TRUSTED_PROXIES").unwrap_or_default())
+                .map_err(|e| format!("AEGIS_TRUSTED_PROXIES: {}", e))?;
+        let threats_path = std::env::var("AEGIS_THREATS_PATH")
+            .unwrap_or_else(|_| "aegis_threats.jsonl".to_string());
+        let threats_retain_days = env_num("AEGIS_THREATS_RETAIN_DAYS", 30);
+        let alerts_path = std::env::var("AEGIS_ALERTS_PATH")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .map(PathBuf::from);
+        let otlp_endpoint = std::env::var("AEGIS_OTLP_ENDPOINT")
+            .ok()
+            .filter(|s| !s.trim().is_empty());
+        let log_format = std::env::var("AEGIS_LOG_FORMAT").unwrap_or_else(|_| "json".to_string());
+        if !matches!(log_format.as_str(), "json" | "text") {
+            return Err(format!("AEGIS_LOG_FORMAT: unknown format {}", log_format));
+        }
         Ok(Self {
             policy_path: PathBuf::from(policy_path),
+            dlp_packs_dir,
+            finding_hmac_key,
             bind,
             audit_path: PathBuf::from(audit_path),
+            audit_on_corrupt,
+            audit_sk_b64,
+            audit_vk_b64,
+            audit_checkpoint_every,
+            audit_rotation,
+            audit_fsync,
+            audit_queue,
+            audit_fail_closed,
+            instance_id,
+            syslog_target,
+            syslog_format,
             artifacts_dir: PathBuf::from(artifacts_dir),
             upstream_override,
             opa_url,
             opa_path,
             sandbox_timeout_ms,
             auth_token,
+            trusted_proxies,
+            threats_path: PathBuf::from(threats_path),
+            threats_retain_days,
+            alerts_path,
+            otlp_endpoint,
+            log_format,
         })
     }
     pub fn bind_addr(&self) -> SocketAddr {
         self.bind
     }
+    pub fn otlp_endpoint(&self) -> Option<&str> {
+        self.otlp_endpoint.as_deref()
+    }
+    pub fn log_format(&self) -> &str {
+        &self.log_format
+    }
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+    pub fn trusted_proxies(&self) -> TrustedProxies {
+        self.trusted_proxies.clone()
+    }
     pub async fn build_state(&self) -> Result<AppState, String> {
         let bytes = fs::read(&self.policy_path).map_err(|e| format!("read policy: {}", e))?;
         let mut policy: Policy =
@@ -119,18 +283,66 @@ impl Config {
         if let Some(u) = &self.upstream_override {
             policy.upstream_base_url = u.clone();
         }
-        let ledger = AuditLedger::new(&self.audit_path);
+        let finding_key = match &self.finding_hmac_key {
+            Some(k) => k.as_bytes().to_vec(),
+            None => {
+                tracing::warn!("AEGIS_FINDING_HMAC_KEY is not set; DLP value hashes will not match across restarts");
+                ChaCha20Poly1305::generate_key(&mut OsRng).to_vec()
+            }
+        };
+        let dlp = DlpRules::load(&self.dlp_packs_dir, finding_key)?;
+        let signer = match &self.audit_sk_b64 {
+            Some(b64) => Some(CheckpointSigner::new(
+                approvals::signing_key_from_b64(b64)
+                    .ok_or("audit signing key: bad base64 ed25519 key")?,
+                self.audit_checkpoint_every,
+            )),
+            None => None,
+        };
+        let verifying_key = match &self.audit_vk_b64 {
+            Some(b64) => Some(
+                approvals::verifying_key_from_b64(b64)
+                    .ok_or("AEGIS_AUDIT_VK_B64: bad verifying key")?,
+            ),
+            None => None,
+        };
+        let ledger = AuditLedger::open(
+            &self.audit_path,
+            LedgerOptions {
+                on_corrupt: self.audit_on_corrupt,
+                signer,
+                verifying_key,
+                checkpoint_every: self.audit_checkpoint_every,
+                rotation: self.audit_rotation.clone(),
+                instance_id: self.instance_id.clone(),
+                forwarder: self
+                    .syslog_target
+                    .clone()
+                    .map(|t| Forwarder::spawn(t, self.syslog_format)),
+                fsync: self.audit_fsync,
+                queue: self.audit_queue,
+                fail_closed: self.audit_fail_closed,
+            },
+        )?;
         let tool_registry = ToolRegistry::from_policy(&policy, &self.artifacts_dir)?;
         let opa = self
             .opa_url
             .as_ref()
             .map(|url| Arc::new(OpaClient::new(url.clone())));
         let upstream = UpstreamClient::new(policy.upstream_base_url.clone());
-        let threats = Arc::new(RwLock::new(VecDeque::new()));
+        let threats = Arc::new(ThreatBus::start(ThreatStore::open(
+            &self.threats_path,
+            self.threats_retain_days,
+        )?));
+        let ledger = Arc::new(ledger);
+        if let Some(path) = &self.alerts_path {
+            Alerter::load(path)?.spawn(&threats, ledger.clone());
+        }
         Ok(AppState {
             policy: Arc::new(policy),
             policy_raw: Arc::new(bytes),
-            ledger: Arc::new(ledger),
+            dlp: Arc::new(dlp),
+            ledger,
             opa,
             opa_path: self.opa_path.clone(),
             upstream: Arc::new(upstream),
@@ -140,6 +352,7 @@ impl Config {
             started_at: OffsetDateTime::now_utc(),
             threats,
             auth_token: self.auth_token.clone(),
+            vault: Arc::new(TokenVault::new()),
         })
     }
 }
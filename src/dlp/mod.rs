@@ -0,0 +1,589 @@
+pub mod checks;
+
+use crate::config::Policy;
+use aho_corasick::AhoCorasick;
+use checks::Check;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FindingKind {
+    Secret,
+    Pii,
+    PromptInjection,
+    Domain,
+}
+
+impl FindingKind {
+    pub fn deny_reason(self) -> &'static str {
+        match self {
+            FindingKind::Secret => "secrets_detected",
+            FindingKind::Pii => "pii_detected",
+            FindingKind::PromptInjection => "prompt_injection",
+            FindingKind::Domain => "domain_not_allowlisted",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// deny the request, or redact when the policy redacts instead of blocking
+    #[default]
+    Block,
+    Redact,
+    Log,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub pattern: String,
+    /// the matched value, mostly masked for secrets and PII
+    #[serde(default)]
+    pub preview: String,
+    /// hex HMAC-SHA256 of the matched value under the finding key, so repeats
+    /// of one value can be correlated without it being stored anywhere
+    #[serde(default)]
+    pub value_hmac: String,
+    /// JSON pointer of the string the match is in, e.g. `/messages/2/content`,
+    /// when a JSON document was scanned; `#key` is appended when the string is
+    /// the object key the pointer ends with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// byte span of the match within that string
+    #[serde(default)]
+    pub start: usize,
+    #[serde(default)]
+    pub end: usize,
+    #[serde(default)]
+    pub severity: String,
+    #[serde(default)]
+    pub action: RuleAction,
+    #[serde(default)]
+    pub confidence: f64,
+}
+
+/// One detector in a rule pack: a regex, or keywords matched case-insensitively.
+/// A regex with a group named `secret` reports only that group, e.g. the
+/// password of a database URL. The reported value must pass every check and
+/// reach `min_entropy` bits per character.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub id: String,
+    pub kind: FindingKind,
+    #[serde(default)]
+    pub regex: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub severity: String,
+    #[serde(default)]
+    pub action: RuleAction,
+    #[serde(default = "default_confidence")]
+    pub confidence: f64,
+    #[serde(default)]
+    pub checks: Vec<Check>,
+    #[serde(default)]
+    pub min_entropy: Option<f64>,
+}
+
+impl Rule {
+    fn accepts(&self, value: &str) -> bool {
+        self.checks.iter().all(|c| c.passes(value))
+            && self
+                .min_entropy
+                .is_none_or(|min| checks::entropy(value) >= min)
+    }
+}
+
+fn default_confidence() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RulePack {
+    pub pack: String,
+    pub version: String,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackInfo {
+    pub pack: String,
+    pub version: String,
+    pub sha256: String,
+}
+
+/// Used when the packs directory holds no `*.pack.json`.
+const BUILTIN_PACK: &str = include_str!("../../policy/packs/builtin.pack.json");
+
+/// Every `*.pack.json` in a directory, compiled once: a `RegexSet` tells in
+/// one pass which regex rules match, so only those are run again to locate
+/// their match, and one Aho-Corasick automaton finds all keywords.
+pub struct DlpEngine {
+    rules: Vec<Rule>,
+    regex_set: RegexSet,
+    /// parallel to `regex_set`: (rule index, compiled pattern, has a `secret` group)
+    regexes: Vec<(usize, Regex, bool)>,
+    keywords: AhoCorasick,
+    /// keyword pattern index -> rule index
+    keyword_rules: Vec<usize>,
+    packs: Vec<PackInfo>,
+    hash: String,
+    finding_key: Vec<u8>,
+}
+
+impl DlpEngine {
+    pub fn load(dir: &Path, finding_key: &[u8]) -> Result<Self, String> {
+        let mut files = vec![];
+        match fs::read_dir(dir) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .is_some_and(|n| n.ends_with(".pack.json"))
+                    {
+                        files.push(path);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("read {}: {}", dir.display(), e)),
+        }
+        files.sort();
+        let mut sources = vec![];
+        for path in files {
+            let bytes = fs::read(&path).map_err(|e| format!("read {}: {}", path.display(), e))?;
+            sources.push((path.display().to_string(), bytes));
+        }
+        if sources.is_empty() {
+            sources.push(("builtin".to_string(), BUILTIN_PACK.as_bytes().to_vec()));
+        }
+        Self::compile(sources, finding_key)
+    }
+
+    fn compile(sources: Vec<(String, Vec<u8>)>, finding_key: &[u8]) -> Result<Self, String> {
+        let mut rules: Vec<Rule> = vec![];
+        let mut regexes = vec![];
+        let mut keywords = vec![];
+        let mut keyword_rules = vec![];
+        let mut packs = vec![];
+        for (name, bytes) in sources {
+            let pack: RulePack =
+                serde_json::from_slice(&bytes).map_err(|e| format!("parse {}: {}", name, e))?;
+            if packs.iter().any(|p: &PackInfo| p.pack == pack.pack) {
+                return Err(format!("{}: pack {} is defined twice", name, pack.pack));
+            }
+            for rule in pack.rules {
+                let err = |msg: &str| format!("{}: rule {}: {}", name, rule.id, msg);
+                if rules.iter().any(|r| r.id == rule.id) {
+                    return Err(err("id is used by another rule"));
+                }
+                if !matches!(
+                    rule.severity.as_str(),
+                    "low" | "medium" | "high" | "critical"
+                ) {
+                    return Err(err("severity must be low, medium, high or critical"));
+                }
+                if !(0.0..=1.0).contains(&rule.confidence) {
+                    return Err(err("confidence must be between 0 and 1"));
+                }
+                if rule.min_entropy.is_some_and(|e| !(0.0..=8.0).contains(&e)) {
+                    return Err(err("min_entropy must be between 0 and 8 bits"));
+                }
+                if rule.kind == FindingKind::Domain {
+                    return Err(err("Domain findings come from allowed_domains"));
+                }
+                if rule.action == RuleAction::Redact
+                    && !matches!(rule.kind, FindingKind::Secret | FindingKind::Pii)
+                {
+                    return Err(err("only Secret and Pii rules can redact"));
+                }
+                match (&rule.regex, rule.keywords.is_empty()) {
+                    (Some(re), true) => {
+                        let re = Regex::new(re).map_err(|e| err(&e.to_string()))?;
+                        let group = re.capture_names().any(|n| n == Some("secret"));
+                        regexes.push((rules.len(), re, group));
+                    }
+                    (None, false) => {
+                        if rule.keywords.iter().any(|k| k.is_empty()) {
+                            return Err(err("keywords must not be empty"));
+                        }
+                        for k in &rule.keywords {
+                            keywords.push(k.clone());
+                            keyword_rules.push(rules.len());
+                        }
+                    }
+                    _ => return Err(err("needs exactly one of regex or keywords")),
+                }
+                rules.push(rule);
+            }
+            packs.push(PackInfo {
+                pack: pack.pack,
+                version: pack.version,
+                sha256: hex::encode(Sha256::digest(&bytes)),
+            });
+        }
+        let regex_set = RegexSet::new(regexes.iter().map(|(_, re, _)| re.as_str()))
+            .map_err(|e| format!("dlp regex set: {}", e))?;
+        let keywords = AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .build(&keywords)
+            .map_err(|e| format!("dlp keywords: {}", e))?;
+        let mut h = Sha256::new();
+        for p in &packs {
+            h.update(format!("{}@{}:{}\n", p.pack, p.version, p.sha256));
+        }
+        Ok(Self {
+            rules,
+            regex_set,
+            regexes,
+            keywords,
+            keyword_rules,
+            packs,
+            hash: hex::encode(h.finalize()),
+            finding_key: finding_key.to_vec(),
+        })
+    }
+
+    pub fn packs(&self) -> &[PackInfo] {
+        &self.packs
+    }
+
+    /// Covers the name, version and exact bytes of every loaded pack.
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    fn value_hmac(&self, value: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.finding_key).expect("hmac accepts any key length");
+        mac.update(value.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Replaces the raw `snippet` of findings recorded before previews existed
+    /// with a preview and HMAC, anywhere in `v`. Returns whether any was found.
+    pub fn scrub_snippets(&self, v: &mut serde_json::Value) -> bool {
+        match v {
+            serde_json::Value::Object(map) => {
+                let mut found = false;
+                if let Some(serde_json::Value::String(snippet)) = map.remove("snippet") {
+                    let kind = map
+                        .get("kind")
+                        .and_then(|k| serde_json::from_value(k.clone()).ok())
+                        .unwrap_or(FindingKind::Secret);
+                    map.insert("preview".into(), preview(kind, &snippet).into());
+                    map.insert("value_hmac".into(), self.value_hmac(&snippet).into());
+                    found = true;
+                }
+                for item in map.values_mut() {
+                    found |= self.scrub_snippets(item);
+                }
+                found
+            }
+            serde_json::Value::Array(items) => items
+                .iter_mut()
+                .fold(false, |found, item| self.scrub_snippets(item) | found),
+            _ => false,
+        }
+    }
+
+    /// A ledger line with legacy raw snippets scrubbed; other lines unchanged.
+    pub fn scrub_line<'a>(&self, line: &'a str) -> std::borrow::Cow<'a, str> {
+        if !line.contains("\"snippet\"") {
+            return line.into();
+        }
+        let Ok(mut v) = serde_json::from_str::<serde_json::Value>(line) else {
+            return line.into();
+        };
+        match self.scrub_snippets(&mut v) {
+            true => v.to_string().into(),
+            false => line.into(),
+        }
+    }
+
+    /// Every match of every rule: by rule, then by position.
+    pub fn scan_all(&self, text: &str) -> Vec<Finding> {
+        let mut spans: Vec<Vec<(usize, usize)>> = vec![vec![]; self.rules.len()];
+        for i in self.regex_set.matches(text).iter() {
+            let (rule, re, group) = &self.regexes[i];
+            if *group {
+                spans[*rule].extend(
+                    re.captures_iter(text)
+                        .filter_map(|c| c.name("secret"))
+                        .map(|m| (m.start(), m.end())),
+                );
+            } else {
+                spans[*rule].extend(re.find_iter(text).map(|m| (m.start(), m.end())));
+            }
+        }
+        for m in self.keywords.find_overlapping_iter(text) {
+            spans[self.keyword_rules[m.pattern().as_usize()]].push((m.start(), m.end()));
+        }
+        let mut out = vec![];
+        for (mut spans, rule) in spans.into_iter().zip(&self.rules) {
+            spans.sort_unstable();
+            spans.dedup();
+            spans.retain(|&(start, end)| rule.accepts(&text[start..end]));
+            out.extend(spans.into_iter().map(|(start, end)| Finding {
+                kind: rule.kind,
+                pattern: rule.id.clone(),
+                preview: preview(rule.kind, &text[start..end]),
+                value_hmac: self.value_hmac(&text[start..end]),
+                path: None,
+                start,
+                end,
+                severity: rule.severity.clone(),
+                action: rule.action,
+                confidence: rule.confidence,
+            }));
+        }
+        out
+    }
+
+    /// Like [`Self::scan_all`], limited to the finding kinds the policy enables.
+    pub fn scan(&self, text: &str, policy: &Policy) -> Vec<Finding> {
+        let mut out = self.scan_all(text);
+        out.retain(|f| match f.kind {
+            FindingKind::Secret => policy.block_on_secrets,
+            FindingKind::PromptInjection => policy.block_on_injection,
+            FindingKind::Pii => policy.block_on_pii,
+            FindingKind::Domain => false,
+        });
+        out
+    }
+
+    /// Scans every string in `doc`, object keys included, tagging findings with
+    /// the string's JSON pointer; spans are relative to that string.
+    pub fn scan_json(&self, doc: &serde_json::Value, policy: &Policy) -> Vec<Finding> {
+        let mut out = vec![];
+        self.scan_value(doc, &mut String::new(), policy, &mut out);
+        out
+    }
+
+    fn scan_value(
+        &self,
+        v: &serde_json::Value,
+        path: &mut String,
+        policy: &Policy,
+        out: &mut Vec<Finding>,
+    ) {
+        let len = path.len();
+        match v {
+            serde_json::Value::String(s) => {
+                for mut f in self.scan(s, policy) {
+                    f.path = Some(path.clone());
+                    out.push(f);
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    path.push_str(&format!("/{}", i));
+                    self.scan_value(item, path, policy, out);
+                    path.truncate(len);
+                }
+            }
+            serde_json::Value::Object(map) => {
+                for (k, item) in map {
+                    path.push('/');
+                    path.push_str(&k.replace('~', "~0").replace('/', "~1"));
+                    for mut f in self.scan(k, policy) {
+                        f.path = Some(format!("{}#key", path));
+                        out.push(f);
+                    }
+                    self.scan_value(item, path, policy, out);
+                    path.truncate(len);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The engine in use, swapped whole when the packs directory is reloaded so
+/// a scan never sees half of a pack set.
+pub struct DlpRules {
+    dir: PathBuf,
+    finding_key: Vec<u8>,
+    engine: RwLock<Arc<DlpEngine>>,
+}
+
+impl DlpRules {
+    pub fn load(dir: &Path, finding_key: Vec<u8>) -> Result<Self, String> {
+        let engine = Arc::new(DlpEngine::load(dir, &finding_key)?);
+        *LOG_ENGINE.write().unwrap() = engine.clone();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            engine: RwLock::new(engine),
+            finding_key,
+        })
+    }
+
+    pub fn engine(&self) -> Arc<DlpEngine> {
+        self.engine.read().unwrap().clone()
+    }
+
+    /// Recompiles the packs; on error the engine in use is kept.
+    pub fn reload(&self) -> Result<Arc<DlpEngine>, String> {
+        let engine = Arc::new(DlpEngine::load(&self.dir, &self.finding_key)?);
+        *self.engine.write().unwrap() = engine.clone();
+        *LOG_ENGINE.write().unwrap() = engine.clone();
+        Ok(engine)
+    }
+}
+
+/// What a finding shows of its match: secrets and PII keep at most a few
+/// leading characters (often just the token type, like `AKIA`) and the last
+/// two; other kinds, such as injection phrases, are kept but cut short.
+fn preview(kind: FindingKind, value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let n = chars.len();
+    if !matches!(kind, FindingKind::Secret | FindingKind::Pii) {
+        return match n > 64 {
+            true => format!("{}…", chars[..64].iter().collect::<String>()),
+            false => value.to_string(),
+        };
+    }
+    let (head, tail) = match n {
+        0..=8 => (0, 0),
+        9..=15 => ((n / 4).min(4), 0),
+        _ => (4, 2),
+    };
+    format!(
+        "{}{}{}",
+        chars[..head].iter().collect::<String>(),
+        "*".repeat((n - head - tail).min(16)),
+        chars[n - tail..].iter().collect::<String>()
+    )
+}
+
+/// The engine [`redact_secrets`] masks with: the one in use once the packs
+/// are loaded, the builtin pack before that.
+static LOG_ENGINE: Lazy<RwLock<Arc<DlpEngine>>> = Lazy::new(|| {
+    let builtin = ("builtin".to_string(), BUILTIN_PACK.as_bytes().to_vec());
+    let engine =
+        DlpEngine::compile(vec![builtin], b"redact_secrets").expect("builtin pack compiles");
+    RwLock::new(Arc::new(engine))
+});
+
+/// Masks every secret the rule packs in use detect, regardless of policy; for
+/// text leaving the gateway by other routes than the API, such as logs.
+pub fn redact_secrets(text: &str) -> String {
+    let engine = LOG_ENGINE.read().unwrap().clone();
+    let mut findings = engine.scan_all(text);
+    findings.retain(|f| f.kind == FindingKind::Secret);
+    if findings.is_empty() {
+        return text.to_string();
+    }
+    replace_spans(text, &findings, |_, _| {
+        Some("[REDACTED_SECRET]".to_string())
+    })
+}
+
+/// Replaces the spans of the findings in `text` with what `with` returns for
+/// them. Overlapping or adjacent spans are merged and replaced as one value,
+/// under a secret finding if one of them is; a merged span with no replacement
+/// is kept, and a finding whose span does not fit `text` is ignored.
+pub fn replace_spans(
+    text: &str,
+    findings: &[Finding],
+    mut with: impl FnMut(&Finding, &str) -> Option<String>,
+) -> String {
+    let mut sorted: Vec<&Finding> = findings
+        .iter()
+        .filter(|f| text.get(f.start..f.end).is_some())
+        .collect();
+    sorted.sort_by_key(|f| (f.start, std::cmp::Reverse(f.end)));
+    let mut out = String::with_capacity(text.len());
+    let mut at = 0;
+    let mut rest = &sorted[..];
+    while let Some(first) = rest.first() {
+        let (start, mut end, mut n) = (first.start, first.end, 1);
+        while let Some(f) = rest.get(n).filter(|f| f.start <= end) {
+            end = end.max(f.end);
+            n += 1;
+        }
+        let (group, next) = rest.split_at(n);
+        rest = next;
+        let f = group
+            .iter()
+            .find(|f| f.kind == FindingKind::Secret)
+            .unwrap_or(first);
+        let Some(replacement) = with(f, &text[start..end]) else {
+            continue;
+        };
+        out.push_str(&text[at..start]);
+        out.push_str(&replacement);
+        at = end;
+    }
+    out.push_str(&text[at..]);
+    out
+}
+
+pub fn redact_text(text: &str, findings: &[Finding]) -> String {
+    replace_spans(text, findings, |f, _| match f.kind {
+        FindingKind::Secret => Some("[REDACTED_SECRET]".to_string()),
+        FindingKind::Pii => Some("[REDACTED_PII]".to_string()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        serde_json::from_str(include_str!("../../policy/packs/policy.json")).unwrap()
+    }
+
+    fn builtin() -> DlpEngine {
+        let pack = ("builtin".to_string(), BUILTIN_PACK.as_bytes().to_vec());
+        DlpEngine::compile(vec![pack], b"test").unwrap()
+    }
+
+    #[test]
+    fn overlapping_findings_are_redacted_as_one() {
+        let pack = br#"{"pack": "t", "version": "1", "rules": [
+            {"id": "head", "kind": "Secret", "regex": "tok-[0-9]{4}", "severity": "high"},
+            {"id": "tail", "kind": "Secret", "regex": "[0-9]{4}-[a-z]{6}", "severity": "high"}
+        ]}"#;
+        let engine = DlpEngine::compile(vec![("t".into(), pack.to_vec())], b"test").unwrap();
+        let text = "use tok-1234-abcdef here";
+        let findings = engine.scan_all(text);
+        assert_eq!(findings.len(), 2);
+        let redacted = redact_text(text, &findings);
+        assert_eq!(redacted, "use [REDACTED_SECRET] here");
+        assert!(engine.scan_all(&redacted).is_empty());
+    }
+
+    #[test]
+    fn scan_json_scans_object_keys() {
+        let key = "sk-live4f9Qz2LmX8pR7tK3vB1nW6yH";
+        let doc = serde_json::json!({"messages": [{"role": "user", "content": "hi", key: 1}]});
+        let findings = builtin().scan_json(&doc, &policy());
+        let f = findings
+            .iter()
+            .find(|f| f.kind == FindingKind::Secret)
+            .expect("secret in a key is found");
+        assert_eq!(
+            f.path.as_deref(),
+            Some(format!("/messages/0/{}#key", key).as_str())
+        );
+        assert_eq!((f.start, f.end), (0, key.len()));
+    }
+}
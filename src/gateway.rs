@@ -1,16 +1,27 @@
 use axum::{
-    extract::{Query, State},
+    extract::{Extension, Query, State},
     http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Response},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     Json,
 };
-use serde::{Deserialize, Serialize};
-use std::io::Write;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::{
+    convert::Infallible,
+    io::{BufRead, BufReader, Read, Write},
+};
 use time::OffsetDateTime;
-use uuid::Uuid;
+use tokio::sync::broadcast;
+use tracing::Instrument;
 use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
 
-use crate::{config::AppState, dlp, opa::OpaError};
+use crate::{
+    audit, client::ClientInfo, config::AppState, dlp, metrics, opa::OpaError, sse, telemetry,
+    threats,
+};
 
 #[derive(Clone)]
 pub struct UpstreamClient {
@@ -24,71 +35,64 @@ impl UpstreamClient {
             http: reqwest::Client::new(),
         }
     }
-    pub async fn forward_chat(
+    async fn send_chat(
         &self,
         body: serde_json::Value,
         auth: Option<&str>,
-    ) -> Result<serde_json::Value, String> {
+    ) -> Result<reqwest::Response, String> {
         let url = format!("{}/v1/chat/completions", self.base.trim_end_matches('/'));
-        let mut r = self.http.post(url).json(&body);
+        let span = tracing::info_span!(
+            "upstream.chat_completions",
+            otel.kind = "client",
+            http.response.status_code = tracing::field::Empty
+        );
+        let mut r = self
+            .http
+            .post(url)
+            .headers(span.in_scope(telemetry::outgoing_headers))
+            .json(&body);
         if let Some(a) = auth {
             r = r.header("Authorization", a);
         }
-        let res = r.send().await.map_err(|e| e.to_string())?;
+        let started = std::time::Instant::now();
+        let res = r.send().instrument(span.clone()).await;
+        if let Ok(res) = &res {
+            span.record("http.response.status_code", res.status().as_u16());
+        }
+        let outcome = match &res {
+            Ok(res) if res.status().is_success() => "ok",
+            Ok(_) => "http_error",
+            Err(_) => "network_error",
+        };
+        metrics::UPSTREAM_LATENCY
+            .with_label_values(&[outcome])
+            .observe(started.elapsed().as_secs_f64());
+        let res = res.map_err(|e| e.to_string())?;
         if !res.status().is_success() {
             return Err(format!("upstream status {}", res.status()));
         }
-        res.json::<serde_json::Value>()
+        Ok(res)
+    }
+    pub async fn forward_chat(
+        &self,
+        body: serde_json::Value,
+        auth: Option<&str>,
+    ) -> Result<serde_json::Value, String> {
+        self.send_chat(body, auth)
+            .await?
+            .json::<serde_json::Value>()
             .await
             .map_err(|e| e.to_string())
     }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Threat {
-    pub id: String,
-    pub ts: String,
-    pub severity: String,
-    pub rule: String,
-    pub src_ip: String,
-    pub dst_ip: String,
-    pub action: String,
-    pub reason: String,
-}
-
-fn demo_threats() -> Vec<Threat> {
-    vec![
-        Threat {
-            id: "t-1001".into(),
-            ts: "2026-01-02T18:00:00Z".into(),
-            severity: "critical".into(),
-            rule: "Deny: Prompt Injection".into(),
-            src_ip: "10.0.0.10".into(),
-            dst_ip: "127.0.0.1".into(),
-            action: "blocked".into(),
-            reason: "policy_denied".into(),
-        },
-        Threat {
-            id: "t-1002".into(),
-            ts: "2026-01-02T17:45:00Z".into(),
-            severity: "high".into(),
-            rule: "Deny: Secrets".into(),
-            src_ip: "10.0.0.5".into(),
-            dst_ip: "127.0.0.1".into(),
-            action: "blocked".into(),
-            reason: "secret_detected".into(),
-        },
-        Threat {
-            id: "t-1003".into(),
-            ts: "2026-01-02T17:30:00Z".into(),
-            severity: "medium".into(),
-            rule: "Deny: Domain".into(),
-            src_ip: "192.168.1.4".into(),
-            dst_ip: "127.0.0.1".into(),
-            action: "blocked".into(),
-            reason: "domain_not_allowlisted".into(),
-        },
-    ]
+    /// Same request as `forward_chat`, but hands back the live response so the
+    /// SSE body can be relayed chunk by chunk.
+    pub async fn forward_chat_stream(
+        &self,
+        body: serde_json::Value,
+        auth: Option<&str>,
+    ) -> Result<reqwest::Response, String> {
+        self.send_chat(body, auth).await
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -131,17 +135,14 @@ pub async fn api_health(State(st): State<AppState>) -> impl IntoResponse {
 }
 
 pub async fn api_status(State(st): State<AppState>) -> impl IntoResponse {
-    let live = st.threats.read().await.clone();
-    let threats: Vec<Threat> = if live.is_empty() {
-        demo_threats()
-    } else {
-        live.into_iter().collect()
+    let day = threats::WINDOWS[1].1;
+    let (blocked, alerts) = {
+        let store = st.threats.store();
+        (
+            store.count(day, &[]),
+            store.count(day, &["critical", "high"]),
+        )
     };
-    let blocked = threats.len() as u64;
-    let alerts = threats
-        .iter()
-        .filter(|t| t.severity == "critical" || t.severity == "high")
-        .count() as u64;
     let uptime_ms = (OffsetDateTime::now_utc() - st.started_at).whole_milliseconds();
     (
         StatusCode::OK,
@@ -149,7 +150,7 @@ pub async fn api_status(State(st): State<AppState>) -> impl IntoResponse {
           "blockedThreats": blocked,
           "alerts": alerts,
           "uptimeMs": uptime_ms,
-          "policy": { "failClosed": st.policy.fail_closed },
+          "policy": { "failClosed": st.policy.fail_closed, "dlpPacks": st.dlp.engine().packs() },
           "auth": if st.auth_token.is_some() { "token" } else { "open" }
         })),
     )
@@ -159,49 +160,346 @@ pub async fn api_threats(
     State(st): State<AppState>,
     Query(q): Query<LimitQuery>,
 ) -> impl IntoResponse {
-    let live: Vec<Threat> = st.threats.read().await.clone().into_iter().collect();
-    let mut items = if live.is_empty() {
-        demo_threats()
-    } else {
-        live
+    let items = st.threats.store().latest(q.limit.unwrap_or(100).min(1000));
+    (StatusCode::OK, Json(items))
+}
+
+pub async fn api_threats_summary(
+    State(st): State<AppState>,
+    Query(q): Query<WindowQuery>,
+) -> impl IntoResponse {
+    let window = q.window.unwrap_or_else(|| "24h".into());
+    let Some(span) = threats::parse_window(&window) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error":"window must be one of 1h, 24h, 7d"})),
+        );
+    };
+    let mut summary = st.threats.store().summary(span);
+    summary["window"] = serde_json::json!(window);
+    (StatusCode::OK, Json(summary))
+}
+
+/// Live threats as server-sent events, one `threat` event each. A client too
+/// slow to keep up gets a `lagged` event with the number it missed.
+pub async fn api_threats_stream(
+    State(st): State<AppState>,
+) -> Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>> {
+    let stream = futures_util::stream::unfold(st.threats.subscribe(), |mut rx| async move {
+        let ev = match rx.recv().await {
+            Ok(t) => Event::default()
+                .event("threat")
+                .data(serde_json::to_string(&t).unwrap_or_default()),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                Event::default().event("lagged").data(n.to_string())
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((Ok(ev), rx))
+    });
+    Sse::new(stream.take_until(st.threats.closed())).keep_alive(KeepAlive::default())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub limit: Option<usize>,
+    pub cursor: Option<u64>,
+    pub event_type: Option<String>,
+    pub request_id: Option<String>,
+    pub kind: Option<String>,
+    /// RFC 3339, inclusive
+    pub from: Option<String>,
+    /// RFC 3339, exclusive
+    pub to: Option<String>,
+}
+
+fn parse_ts_ms(field: &str, v: &Option<String>) -> Result<Option<i64>, String> {
+    v.as_deref()
+        .map(|s| {
+            OffsetDateTime::parse(s, &time::format_description::well_known::Rfc3339)
+                .map(|t| (t.unix_timestamp_nanos() / 1_000_000) as i64)
+                .map_err(|e| format!("invalid {}: {}", field, e))
+        })
+        .transpose()
+}
+
+pub async fn api_audit(
+    State(st): State<AppState>,
+    Query(q): Query<AuditQuery>,
+) -> impl IntoResponse {
+    let limit = q.limit.unwrap_or(50).clamp(1, 500);
+    let (from_ms, to_ms) = match (parse_ts_ms("from", &q.from), parse_ts_ms("to", &q.to)) {
+        (Ok(f), Ok(t)) => (f, t),
+        (Err(e), _) | (_, Err(e)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": e})),
+            )
+        }
     };
-    if let Some(lim) = q.limit {
-        if lim < items.len() {
-            items.truncate(lim);
+    let filter = audit::Filter {
+        event_type: q.event_type,
+        request_id: q.request_id,
+        kind: q.kind,
+        from_ms,
+        to_ms,
+        before_seq: q.cursor,
+    };
+    let ledger = st.ledger.clone();
+    match tokio::task::spawn_blocking(move || ledger.query(&filter, limit)).await {
+        Ok(Ok((mut events, next_cursor))) => {
+            let dlp = st.dlp.engine();
+            for ev in &mut events {
+                dlp.scrub_snippets(ev);
+            }
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({ "events": events, "next_cursor": next_cursor })),
+            )
         }
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        ),
+    }
+}
+
+pub async fn api_audit_verify(
+    State(st): State<AppState>,
+    client: Option<Extension<ClientInfo>>,
+) -> impl IntoResponse {
+    let ledger = st.ledger.clone();
+    match tokio::task::spawn_blocking(move || ledger.verify()).await {
+        Ok(Ok(report)) => {
+            if !report.ok {
+                let client = client.map(|c| c.0).unwrap_or_default();
+                let src = threats::ThreatSource {
+                    client: &client,
+                    request_id: "",
+                    model: None,
+                };
+                threats::record(
+                    &st,
+                    &src,
+                    "critical",
+                    "Tamper: Audit Ledger",
+                    "chain_broken",
+                    &[],
+                );
+            }
+            (StatusCode::OK, Json(serde_json::json!(report)))
+        }
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TreeHeadQuery {
+    pub tree_size: Option<u64>,
+}
+
+pub async fn api_merkle_sth(
+    State(st): State<AppState>,
+    Query(q): Query<TreeHeadQuery>,
+) -> impl IntoResponse {
+    match st.ledger.tree_head(q.tree_size) {
+        Ok(sth) => (StatusCode::OK, Json(sth)),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e})),
+        ),
     }
-    (StatusCode::OK, Json(items))
 }
 
-pub async fn api_threats_summary(Query(q): Query<WindowQuery>) -> impl IntoResponse {
-    let _window = q.window.unwrap_or_else(|| "24h".into());
-    let threats = demo_threats();
-    let mut sev = std::collections::HashMap::new();
-    for t in threats {
-        *sev.entry(t.severity).or_insert(0u64) += 1;
+#[derive(Debug, Deserialize)]
+pub struct InclusionQuery {
+    pub seq: Option<u64>,
+    pub request_id: Option<String>,
+    pub event_type: Option<String>,
+    pub tree_size: Option<u64>,
+}
+
+/// Inclusion proofs for one record (`seq`) or every record of a request,
+/// all against the same signed tree head.
+pub async fn api_merkle_inclusion(
+    State(st): State<AppState>,
+    Query(q): Query<InclusionQuery>,
+) -> impl IntoResponse {
+    let filter = match (q.seq, q.request_id) {
+        (Some(seq), _) => audit::Filter {
+            before_seq: Some(seq.saturating_add(1)),
+            ..Default::default()
+        },
+        (None, Some(request_id)) => audit::Filter {
+            request_id: Some(request_id),
+            event_type: q.event_type,
+            ..Default::default()
+        },
+        (None, None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": "seq or request_id required"})),
+            )
+        }
+    };
+    let sth = match st.ledger.tree_head(q.tree_size) {
+        Ok(sth) => sth,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": e})),
+            )
+        }
+    };
+    let tree_size = sth["tree_size"].as_u64().unwrap_or(0);
+    let limit = if q.seq.is_some() { 1 } else { 500 };
+    let ledger = st.ledger.clone();
+    let events = match tokio::task::spawn_blocking(move || ledger.query(&filter, limit)).await {
+        Ok(Ok((events, _))) => events,
+        Ok(Err(e)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+        }
+    };
+    let mut proofs = vec![];
+    let dlp = st.dlp.engine();
+    for mut ev in events {
+        // the proof covers `hash`, which a scrubbed event no longer hashes to:
+        // its content cannot be checked against the proof, only its hash
+        let scrubbed = dlp.scrub_snippets(&mut ev);
+        let seq = ev["seq"].as_u64().unwrap_or(0);
+        if q.seq.is_some_and(|want| want != seq) || seq > tree_size {
+            continue;
+        }
+        match st.ledger.inclusion_proof(seq, tree_size) {
+            Ok(path) => proofs.push(serde_json::json!({
+                "seq": seq,
+                "leaf_index": seq - 1,
+                "event": ev,
+                "content_verifiable": !scrubbed,
+                "audit_path": path,
+            })),
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({"error": e})),
+                )
+            }
+        }
+    }
+    if proofs.is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "no matching record in the tree"})),
+        );
     }
     (
         StatusCode::OK,
-        Json(serde_json::json!({
-          "bySeverity": sev,
-          "window": "24h"
-        })),
+        Json(serde_json::json!({"tree_head": sth, "proofs": proofs})),
     )
 }
 
-pub async fn api_audit(
+#[derive(Debug, Deserialize)]
+pub struct ConsistencyQuery {
+    pub first: u64,
+    pub second: Option<u64>,
+}
+
+pub async fn api_merkle_consistency(
     State(st): State<AppState>,
-    Query(q): Query<LimitQuery>,
+    Query(q): Query<ConsistencyQuery>,
 ) -> impl IntoResponse {
-    let limit = q.limit.unwrap_or(50);
-    let content = st.ledger.export_all();
-    let mut lines: Vec<_> = content.lines().rev().take(limit).collect();
-    lines.reverse();
-    (StatusCode::OK, Json(serde_json::json!({ "lines": lines })))
+    let result = st.ledger.tree_head(q.second).and_then(|sth| {
+        let second = sth["tree_size"].as_u64().unwrap_or(0);
+        let first_head = st.ledger.tree_head(Some(q.first))?;
+        let proof = st.ledger.consistency_proof(q.first, second)?;
+        Ok(serde_json::json!({
+            "first": q.first,
+            "second": second,
+            "first_root": first_head["root_hash"],
+            "second_root": sth["root_hash"],
+            "proof": proof,
+            "tree_head": sth,
+        }))
+    });
+    match result {
+        Ok(v) => (StatusCode::OK, Json(v)),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e})),
+        ),
+    }
 }
 
-pub async fn export_audit(State(st): State<AppState>) -> impl IntoResponse {
-    (StatusCode::OK, st.ledger.export_all())
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    /// jsonl (default), ocsf, cef or syslog
+    pub format: Option<String>,
+}
+
+pub async fn export_audit(State(st): State<AppState>, Query(q): Query<ExportQuery>) -> Response {
+    let format = match audit::siem::Format::parse(q.format.as_deref().unwrap_or("")) {
+        Ok(f) => f,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": e})),
+            )
+                .into_response()
+        }
+    };
+    let dlp = st.dlp.engine();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", format.content_type())
+        .body(stream_reader(st.ledger.export(format, move |line| {
+            dlp.scrub_line(line).into_owned()
+        })))
+        .unwrap()
+}
+
+/// Streams a blocking reader (e.g. the segmented ledger) as a response body
+/// without buffering it whole.
+pub fn stream_reader<R: Read + Send + 'static>(mut reader: R) -> axum::body::Body {
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<bytes::Bytes>>(4);
+    tokio::task::spawn_blocking(move || loop {
+        let mut buf = vec![0u8; 64 * 1024];
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                buf.truncate(n);
+                if tx.blocking_send(Ok(buf.into())).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                let _ = tx.blocking_send(Err(e));
+                break;
+            }
+        }
+    });
+    axum::body::Body::from_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }))
 }
 
 fn opa_fail_closed(st: &AppState, e: &OpaError) -> bool {
@@ -211,73 +509,116 @@ fn opa_fail_closed(st: &AppState, e: &OpaError) -> bool {
     }
 }
 
-fn record_threat(st: &AppState, sev: &str, rule: &str, reason: &str) {
-    let mut buf = st.threats.blocking_write();
-    if buf.len() > 999 {
-        buf.pop_front();
-    }
-    buf.push_back(Threat {
-        id: format!("t-{}", Uuid::new_v4()),
-        ts: OffsetDateTime::now_utc()
-            .format(&time::format_description::well_known::Rfc3339)
-            .unwrap_or_else(|_| "now".into()),
-        severity: sev.to_string(),
-        rule: rule.to_string(),
-        src_ip: "127.0.0.1".to_string(),
-        dst_ip: "127.0.0.1".to_string(),
-        action: "blocked".to_string(),
-        reason: reason.to_string(),
-    });
-}
-
 pub async fn chat_completions(
     State(st): State<AppState>,
+    Extension(crate::RequestId(request_id)): Extension<crate::RequestId>,
+    client: Option<Extension<ClientInfo>>,
     headers: HeaderMap,
-    Json(req): Json<serde_json::Value>,
-) -> impl IntoResponse {
-    let request_id = Uuid::new_v4().to_string();
+    Json(mut req): Json<serde_json::Value>,
+) -> Result<Response, audit::AuditError> {
+    let client = client.map(|c| c.0).unwrap_or_default();
+    let model = req["model"].as_str().map(str::to_string);
 
-    let raw = serde_json::to_string(&req).unwrap_or_default();
-    let findings = dlp::scan_text(&raw, &st.policy);
-    st.ledger.append(
-        "prompt.scan",
-        &request_id,
-        serde_json::json!({"findings": findings}),
-    );
+    let dlp = st.dlp.engine();
+    let mut findings = dlp.scan_json(&req, &st.policy);
+    metrics::dlp_findings("prompt", &findings);
+    st.ledger
+        .append(
+            "prompt.scan",
+            &request_id,
+            serde_json::json!({"findings": findings}),
+        )
+        .await?;
+
+    let purge = st.vault.purge_on_drop(&request_id);
+    if findings
+        .iter()
+        .any(|f| redacts(f, st.policy.redact_before_upstream))
+    {
+        let redactions = redact_request(&st, &request_id, &mut req, &findings);
+        if !redactions.is_empty() {
+            st.ledger
+                .append(
+                    "prompt.redact",
+                    &request_id,
+                    serde_json::json!({ "redactions": redactions }),
+                )
+                .await?;
+            // whatever could not be redacted is still subject to the deny rules below
+            findings = dlp.scan_json(&req, &st.policy);
+        }
+    }
 
-    for f in &findings {
+    let source = threats::ThreatSource {
+        client: &client,
+        request_id: &request_id,
+        model: model.as_deref(),
+    };
+    let of_kind = |kind: dlp::FindingKind| -> Vec<dlp::Finding> {
+        findings
+            .iter()
+            .filter(|f| f.kind == kind)
+            .cloned()
+            .collect()
+    };
+    // anything that should have been redacted but is still there is denied
+    for f in findings.iter().filter(|f| {
+        f.action == dlp::RuleAction::Block || redacts(f, st.policy.redact_before_upstream)
+    }) {
         match f.kind {
             dlp::FindingKind::Secret if st.policy.block_on_secrets => {
-                st.ledger.append(
-                    "prompt.deny",
-                    &request_id,
-                    serde_json::json!({"reason":"secrets_detected"}),
+                st.ledger
+                    .append(
+                        "prompt.deny",
+                        &request_id,
+                        serde_json::json!({"reason":"secrets_detected"}),
+                    )
+                    .await?;
+                threats::record(
+                    &st,
+                    &source,
+                    &f.severity,
+                    "Deny: Secrets",
+                    "secret_detected",
+                    &of_kind(f.kind),
                 );
-                record_threat(&st, "high", "Deny: Secrets", "secret_detected");
-                return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"denied","reason":"secrets_detected","request_id":request_id}))).into_response();
+                return Ok((StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"denied","reason":"secrets_detected","request_id":request_id}))).into_response());
             }
             dlp::FindingKind::PromptInjection if st.policy.block_on_injection => {
-                st.ledger.append(
-                    "prompt.deny",
-                    &request_id,
-                    serde_json::json!({"reason":"prompt_injection"}),
-                );
-                record_threat(
+                st.ledger
+                    .append(
+                        "prompt.deny",
+                        &request_id,
+                        serde_json::json!({"reason":"prompt_injection"}),
+                    )
+                    .await?;
+                threats::record(
                     &st,
-                    "critical",
+                    &source,
+                    &f.severity,
                     "Deny: Prompt Injection",
                     "prompt_injection",
+                    &of_kind(f.kind),
                 );
-                return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"denied","reason":"prompt_injection","request_id":request_id}))).into_response();
+                return Ok((StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"denied","reason":"prompt_injection","request_id":request_id}))).into_response());
             }
             dlp::FindingKind::Pii if st.policy.block_on_pii => {
-                st.ledger.append(
-                    "prompt.deny",
-                    &request_id,
-                    serde_json::json!({"reason":"pii_detected"}),
+                st.ledger
+                    .append(
+                        "prompt.deny",
+                        &request_id,
+                        serde_json::json!({"reason":"pii_detected"}),
+                    )
+                    .await?;
+                threats::record(
+                    &st,
+                    &source,
+                    &f.severity,
+                    "Deny: PII",
+                    "pii_detected",
+                    &of_kind(f.kind),
                 );
-                record_threat(&st, "medium", "Deny: PII", "pii_detected");
-                return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"denied","reason":"pii_detected","request_id":request_id}))).into_response();
+                return Ok((StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"denied","reason":"pii_detected","request_id":request_id}))).into_response());
             }
             _ => {}
         }
@@ -287,38 +628,254 @@ pub async fn chat_completions(
         let input =
             serde_json::json!({"kind":"prompt","request_id":request_id,"findings":findings});
         if let Err(e) = opa.require_allow(&st.opa_path, input).await {
-            st.ledger.append(
-                "prompt.denied",
-                &request_id,
-                serde_json::json!({"reason": e.to_string()}),
-            );
+            st.ledger
+                .append(
+                    "prompt.denied",
+                    &request_id,
+                    serde_json::json!({"reason": e.to_string()}),
+                )
+                .await?;
             if opa_fail_closed(&st, &e) {
-                return (
+                return Ok((
                     StatusCode::FORBIDDEN,
                     Json(serde_json::json!({"error":"Blocked by policy","request_id":request_id})),
                 )
-                    .into_response();
+                    .into_response());
             }
         }
     }
 
     let auth = headers.get("authorization").and_then(|v| v.to_str().ok());
 
+    if req.get("stream").and_then(|v| v.as_bool()).unwrap_or(false) {
+        return match st.upstream.forward_chat_stream(req, auth).await {
+            Ok(res) if sse::is_event_stream(&res) => {
+                Ok(sse::proxy_chat_stream(st.clone(), request_id, res, purge))
+            }
+            // a complete body answering a stream request is scanned like any other
+            Ok(res) => match res.json::<serde_json::Value>().await {
+                Ok(v) => completion_response(&st, &request_id, v).await,
+                Err(e) => Ok(upstream_error(&st, &request_id, e.to_string()).await),
+            },
+            Err(e) => Ok(upstream_error(&st, &request_id, e).await),
+        };
+    }
+
     match st.upstream.forward_chat(req, auth).await {
-        Ok(v) => (StatusCode::OK, Json(v)).into_response(),
-        Err(e) => {
-            st.ledger.append(
-                "upstream.error",
-                &request_id,
-                serde_json::json!({"error": e}),
-            );
-            (
-                StatusCode::BAD_GATEWAY,
-                Json(serde_json::json!({"error":"upstream error","request_id":request_id})),
-            )
-                .into_response()
+        Ok(v) => completion_response(&st, &request_id, v).await,
+        Err(e) => Ok(upstream_error(&st, &request_id, e).await),
+    }
+}
+
+async fn completion_response(
+    st: &AppState,
+    request_id: &str,
+    mut v: serde_json::Value,
+) -> Result<Response, audit::AuditError> {
+    if let Some(reason) = scan_response(st, request_id, &mut v).await? {
+        return Ok((StatusCode::FORBIDDEN, Json(serde_json::json!({"error":"response blocked","reason":reason,"request_id":request_id}))).into_response());
+    }
+    for (_, field) in response_fields(&mut v) {
+        if let Some(text) = field.as_str() {
+            *field = serde_json::Value::String(st.vault.detokenize(request_id, text));
+        }
+    }
+    Ok((StatusCode::OK, Json(v)).into_response())
+}
+
+/// Whether `f` is redacted rather than blocked or only logged: its rule always
+/// redacts, or it would block but the policy redacts secrets and PII instead.
+fn redacts(f: &dlp::Finding, policy_redacts: bool) -> bool {
+    matches!(f.kind, dlp::FindingKind::Secret | dlp::FindingKind::Pii)
+        && match f.action {
+            dlp::RuleAction::Redact => true,
+            dlp::RuleAction::Block => policy_redacts,
+            dlp::RuleAction::Log => false,
+        }
+}
+
+/// Redacts the secret and PII `findings` in place, wherever in the request
+/// body their JSON pointer leads, and returns where each redaction happened.
+/// Never the value. With `tokenize_redactions` the values go to the vault
+/// behind placeholders.
+fn redact_request(
+    st: &AppState,
+    request_id: &str,
+    req: &mut serde_json::Value,
+    findings: &[dlp::Finding],
+) -> Vec<serde_json::Value> {
+    let mut by_path: std::collections::BTreeMap<&str, Vec<dlp::Finding>> = Default::default();
+    for f in findings
+        .iter()
+        .filter(|f| redacts(f, st.policy.redact_before_upstream))
+    {
+        if let Some(path) = f.path.as_deref() {
+            by_path.entry(path).or_default().push(f.clone());
+        }
+    }
+    let mut out = vec![];
+    for (path, found) in by_path {
+        // object keys (`#key`) are not rewritten; those findings are denied instead
+        let Some(field) = req.pointer_mut(path) else {
+            continue;
+        };
+        let Some(text) = field.as_str() else {
+            continue;
+        };
+        let redacted = if st.policy.tokenize_redactions {
+            st.vault.tokenize(request_id, text, &found)
+        } else {
+            dlp::redact_text(text, &found)
+        };
+        *field = serde_json::Value::String(redacted);
+        for f in found {
+            out.push(serde_json::json!({"path": path, "start": f.start, "end": f.end, "kind": f.kind, "pattern": f.pattern}));
+        }
+    }
+    out
+}
+
+/// The model-authored strings of a chat completion with their JSON pointers:
+/// message contents (plain or text parts) and tool/function call arguments.
+fn response_fields(resp: &mut serde_json::Value) -> Vec<(String, &mut serde_json::Value)> {
+    let mut out = vec![];
+    let Some(choices) = resp.get_mut("choices").and_then(|c| c.as_array_mut()) else {
+        return out;
+    };
+    for (i, choice) in choices.iter_mut().enumerate() {
+        let Some(msg) = choice.get_mut("message").and_then(|m| m.as_object_mut()) else {
+            continue;
+        };
+        let base = format!("/choices/{}/message", i);
+        for (k, v) in msg.iter_mut() {
+            match k.as_str() {
+                "content" if v.is_array() => {
+                    for (j, part) in v.as_array_mut().into_iter().flatten().enumerate() {
+                        if let Some(t) = part.get_mut("text") {
+                            out.push((format!("{}/content/{}/text", base, j), t));
+                        }
+                    }
+                }
+                "content" => out.push((format!("{}/content", base), v)),
+                "tool_calls" => {
+                    for (j, call) in v.as_array_mut().into_iter().flatten().enumerate() {
+                        if let Some(a) = call.pointer_mut("/function/arguments") {
+                            out.push((format!("{}/tool_calls/{}/function/arguments", base, j), a));
+                        }
+                    }
+                }
+                "function_call" => {
+                    if let Some(a) = v.get_mut("arguments") {
+                        out.push((format!("{}/function_call/arguments", base), a));
+                    }
+                }
+                _ => {}
+            }
         }
     }
+    out
+}
+
+/// Scans the upstream answer, redacting it in place when the policy asks for it.
+/// Returns the deny reason if the response must not reach the client.
+async fn scan_response(
+    st: &AppState,
+    request_id: &str,
+    resp: &mut serde_json::Value,
+) -> Result<Option<&'static str>, audit::AuditError> {
+    let redact = st.policy.redact_response_to_client;
+    let dlp = st.dlp.engine();
+    let mut findings = vec![];
+    let mut unredacted = vec![];
+    for (path, field) in response_fields(resp) {
+        let Some(text) = field.as_str() else {
+            continue;
+        };
+        let mut found = dlp.scan(text, &st.policy);
+        for f in &mut found {
+            f.path = Some(path.clone());
+        }
+        if found.is_empty() {
+            continue;
+        }
+        let redactable: Vec<dlp::Finding> = found
+            .iter()
+            .filter(|f| redacts(f, redact))
+            .cloned()
+            .collect();
+        if !redactable.is_empty() {
+            let redacted = dlp::redact_text(text, &redactable);
+            // anything the redaction did not cover blocks the response
+            for mut f in dlp.scan(&redacted, &st.policy) {
+                if redacts(&f, redact) {
+                    f.path = Some(path.clone());
+                    unredacted.push(f);
+                }
+            }
+            *field = serde_json::Value::String(redacted);
+        }
+        findings.extend(found);
+    }
+    metrics::dlp_findings("response", &findings);
+
+    // only secrets and PII can be redacted; anything else that blocks still does
+    let reason = findings
+        .iter()
+        .find(|f| f.action == dlp::RuleAction::Block && !redacts(f, redact))
+        .or(unredacted.first())
+        .map(|f| f.kind.deny_reason());
+    st.ledger
+        .append(
+            "response.scan",
+            request_id,
+            serde_json::json!({
+                "findings": findings,
+                "redacted": findings.iter().any(|f| redacts(f, redact)),
+                "unredacted": unredacted,
+                "blocked": reason.is_some()
+            }),
+        )
+        .await?;
+    if let Some(reason) = reason {
+        st.ledger
+            .append(
+                "response.deny",
+                request_id,
+                serde_json::json!({ "reason": reason }),
+            )
+            .await?;
+    }
+    Ok(reason)
+}
+
+async fn upstream_error(st: &AppState, request_id: &str, e: String) -> Response {
+    tracing::warn!(error = %e, "upstream request failed");
+    if let Err(e) = st
+        .ledger
+        .append(
+            "upstream.error",
+            request_id,
+            serde_json::json!({"error": e}),
+        )
+        .await
+    {
+        return e.into_response();
+    }
+    (
+        StatusCode::BAD_GATEWAY,
+        Json(serde_json::json!({"error":"upstream error","request_id":request_id})),
+    )
+        .into_response()
+}
+
+impl IntoResponse for audit::AuditError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"error":"audit unavailable","detail":self.0})),
+        )
+            .into_response()
+    }
 }
 
 pub async fn support_bundle(
@@ -367,16 +924,21 @@ pub async fn support_bundle(
         let _ = zip.start_file("policy_snapshot.json", opts);
         let _ = zip.write(policy_json.as_bytes());
 
-        let audit_raw = st.ledger.export_all();
-        let audit = audit_raw
-            .lines()
-            .map(|ln| ln.replace("Authorization", "Authorization: [REDACTED]"))
-            .collect::<Vec<_>>()
-            .join("\n");
         let _ = zip.start_file("audit.jsonl", opts);
-        let _ = zip.write(audit.as_bytes());
+        let dlp = st.dlp.engine();
+        for ln in BufReader::new(st.ledger.reader())
+            .lines()
+            .map_while(Result::ok)
+        {
+            let _ = zip.write_all(
+                dlp.scrub_line(&ln)
+                    .replace("Authorization", "Authorization: [REDACTED]")
+                    .as_bytes(),
+            );
+            let _ = zip.write_all(b"\n");
+        }
 
-        let threats: Vec<Threat> = st.threats.read().await.clone().into_iter().collect();
+        let threats = st.threats.store().latest(1000);
         let threats_json = serde_json::to_string_pretty(&threats).unwrap_or_default();
         let _ = zip.start_file("threats.json", opts);
         let _ = zip.write(threats_json.as_bytes());
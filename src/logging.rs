@@ -0,0 +1,99 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::io::{self, Write};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::dlp;
+
+/// Fields whose values never reach the log, whatever they contain. Matched on
+/// the last dotted segment, so `http.request.header.authorization` counts.
+const SENSITIVE_FIELDS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "api_key",
+    "token",
+    "password",
+    "secret",
+    "prompt",
+    "content",
+    "messages",
+    "body",
+];
+
+static CREDENTIALS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\b(bearer|basic)\s+[A-Za-z0-9._~+/=-]+").unwrap());
+
+fn sensitive(key: &str) -> bool {
+    let last = key.rsplit('.').next().unwrap_or(key).to_ascii_lowercase();
+    SENSITIVE_FIELDS.contains(&last.as_str())
+}
+
+fn redact_str(s: &str) -> String {
+    let s = dlp::redact_secrets(s);
+    CREDENTIALS.replace_all(&s, "$1 [REDACTED]").into_owned()
+}
+
+fn redact_value(v: &mut Value) {
+    match v {
+        Value::String(s) => *s = redact_str(s),
+        Value::Array(items) => items.iter_mut().for_each(redact_value),
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                if sensitive(k) {
+                    *v = Value::String("[REDACTED]".into());
+                } else {
+                    redact_value(v);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// One formatted log line with sensitive fields blanked and secrets masked;
+/// JSON lines are rewritten field by field, anything else as plain text.
+pub fn redact_line(line: &str) -> String {
+    match serde_json::from_str::<Value>(line) {
+        Ok(mut v) if v.is_object() => {
+            redact_value(&mut v);
+            format!("{}\n", v)
+        }
+        _ => redact_str(line),
+    }
+}
+
+/// Writer for the fmt layer: buffers each event and writes it to stdout only
+/// after redaction, so nothing unredacted is ever emitted.
+pub struct Redacting;
+
+pub struct RedactingWriter(Vec<u8>);
+
+impl<'a> MakeWriter<'a> for Redacting {
+    type Writer = RedactingWriter;
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(Vec::with_capacity(256))
+    }
+}
+
+impl Write for RedactingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RedactingWriter {
+    fn drop(&mut self) {
+        if self.0.is_empty() {
+            return;
+        }
+        let line = redact_line(&String::from_utf8_lossy(&self.0));
+        let _ = io::stdout().lock().write_all(line.as_bytes());
+    }
+}
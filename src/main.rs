@@ -1,13 +1,22 @@
+mod alerts;
 mod approvals;
 mod audit;
 mod bundle;
+mod cli;
+mod client;
 mod config;
 mod decision;
 mod dlp;
 mod gateway;
+mod logging;
+mod metrics;
 mod opa;
+mod sse;
+mod telemetry;
+mod threats;
 mod tools;
 mod ui;
+mod vault;
 
 use axum::http::{HeaderValue, Request};
 use axum::{
@@ -24,9 +33,10 @@ use std::{
     time::{Duration, Instant},
 };
 use tower_http::cors::{Any, CorsLayer};
+use tracing::Instrument;
 use uuid::Uuid;
 
-async fn auth_middleware(token: Option<String>, req: Request<Body>, next: Next) -> Response {
+async fn auth_middleware(token: Option<String>, mut req: Request<Body>, next: Next) -> Response {
     // allow-list public routes
     let path = req.uri().path();
     let public = matches!(
@@ -46,7 +56,10 @@ async fn auth_middleware(token: Option<String>, req: Request<Body>, next: Next)
         .get("authorization")
         .and_then(|v| v.to_str().ok())
     {
-        if h == format!("Bearer {}", token.clone().unwrap()) {
+        let token = token.unwrap();
+        if h == format!("Bearer {}", token) {
+            req.extensions_mut()
+                .insert(client::Principal(client::token_principal(&token)));
             return next.run(req).await;
         }
     }
@@ -109,20 +122,93 @@ impl RateLimiter {
 
 fn client_ip<B>(req: &Request<B>) -> Option<IpAddr> {
     req.extensions()
+        .get::<client::ClientInfo>()
+        .and_then(|c| c.ip)
+}
+
+/// Resolves the real client behind any trusted proxies, and who it is.
+async fn client_middleware(
+    axum::extract::State(trusted): axum::extract::State<client::TrustedProxies>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let peer = req
+        .extensions()
         .get::<axum::extract::ConnectInfo<SocketAddr>>()
-        .map(|ci| ci.0.ip())
+        .map(|ci| ci.0.ip());
+    let info = client::ClientInfo {
+        ip: peer.map(|p| trusted.client_ip(p, req.headers())),
+        principal: peer
+            .and_then(|p| trusted.forwarded_user(p, req.headers()))
+            .or_else(|| {
+                req.extensions()
+                    .get::<client::Principal>()
+                    .map(|p| p.0.clone())
+            }),
+    };
+    req.extensions_mut().insert(info);
+    next.run(req).await
 }
 
+/// The id the gateway mints for every request. It keys ledger records, vault
+/// entries and artifact directories, so it never comes from the caller.
 #[derive(Clone)]
 struct RequestId(pub String);
 
+/// Incoming `x-request-id` values are kept as a correlation id when they are
+/// short and plain.
+fn acceptable_request_id(id: &str) -> bool {
+    id.len() <= 128
+        && id.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
 async fn request_id_middleware(req: Request<Body>, next: Next) -> Response {
     let mut req = req;
     let rid = Uuid::new_v4().to_string();
+    let correlation_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| acceptable_request_id(id))
+        .map(str::to_string);
     req.extensions_mut().insert(RequestId(rid.clone()));
-    let mut res = next.run(req).await;
+    let route = req
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "http.request",
+        otel.name = format!("{} {}", req.method(), route),
+        otel.kind = "server",
+        http.request.method = %req.method(),
+        http.route = route,
+        request_id = rid,
+        correlation_id = correlation_id.as_deref(),
+        http.response.status_code = tracing::field::Empty,
+    );
+    telemetry::set_remote_parent(&span, req.headers());
+    let started = Instant::now();
+    let http_id = correlation_id.clone().unwrap_or_else(|| rid.clone());
+    let handled = audit::HTTP_REQUEST_ID.scope(http_id, next.run(req).instrument(span.clone()));
+    let mut res = audit::REQUEST_ID.scope(rid.clone(), handled).await;
+    span.record("http.response.status_code", res.status().as_u16());
+    span.in_scope(|| {
+        tracing::info!(
+            status = res.status().as_u16(),
+            latency_ms = started.elapsed().as_millis() as u64,
+            "request completed"
+        )
+    });
     res.headers_mut()
         .insert("x-request-id", HeaderValue::from_str(&rid).unwrap());
+    if let Some(id) = correlation_id {
+        res.headers_mut()
+            .insert("x-correlation-id", HeaderValue::from_str(&id).unwrap());
+    }
     res
 }
 
@@ -140,6 +226,7 @@ async fn rate_limit_middleware(
         };
         let (ok, retry_after) = limiter.allow(ip, cost);
         if !ok {
+            metrics::RATE_LIMITED.inc();
             let mut resp = Response::new(axum::body::Body::from("rate limit exceeded"));
             *resp.status_mut() = axum::http::StatusCode::TOO_MANY_REQUESTS;
             resp.headers_mut().insert(
@@ -152,9 +239,44 @@ async fn rate_limit_middleware(
     Ok(next.run(req).await)
 }
 
+/// Recompiles the DLP rule packs on SIGHUP; a pack set that fails to load
+/// leaves the current rules in place.
+async fn reload_dlp_on_hangup(dlp: Arc<dlp::DlpRules>, ledger: Arc<audit::AuditLedger>) {
+    let Ok(mut hangup) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+    else {
+        return;
+    };
+    while hangup.recv().await.is_some() {
+        match dlp.reload() {
+            Ok(engine) => {
+                tracing::info!(
+                    hash = engine.hash(),
+                    rules = engine.rule_count(),
+                    "dlp rule packs reloaded"
+                );
+                let _ = ledger
+                    .append(
+                        "dlp.reload",
+                        audit::LEDGER_REQUEST_ID,
+                        serde_json::json!({"packs": engine.packs(), "hash": engine.hash()}),
+                    )
+                    .await;
+            }
+            Err(e) => tracing::error!(error = %e, "dlp rule pack reload failed"),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
     let cfg = config::Config::load().expect("config load failed");
+    let telemetry = telemetry::init(cfg.otlp_endpoint(), cfg.instance_id(), cfg.log_format())
+        .expect("telemetry init failed");
+    metrics::init();
     let state = cfg.build_state().await.expect("state init failed");
 
     let cors = CorsLayer::new()
@@ -163,6 +285,9 @@ async fn main() {
         .allow_methods(Any);
 
     let auth_token = state.auth_token.clone();
+    let ledger = state.ledger.clone();
+    let threats = state.threats.clone();
+    tokio::spawn(reload_dlp_on_hangup(state.dlp.clone(), ledger.clone()));
     let limiter = RateLimiter::new(30.0, 60.0);
 
     let app = Router::new()
@@ -175,8 +300,20 @@ async fn main() {
         .route("/api/v1/status", get(gateway::api_status))
         .route("/api/v1/threats", get(gateway::api_threats))
         .route("/api/v1/threats/summary", get(gateway::api_threats_summary))
+        .route("/api/v1/threats/stream", get(gateway::api_threats_stream))
         .route("/api/v1/audit", get(gateway::api_audit))
+        .route("/api/v1/audit/verify", get(gateway::api_audit_verify))
+        .route("/api/v1/audit/merkle/sth", get(gateway::api_merkle_sth))
+        .route(
+            "/api/v1/audit/merkle/inclusion",
+            get(gateway::api_merkle_inclusion),
+        )
+        .route(
+            "/api/v1/audit/merkle/consistency",
+            get(gateway::api_merkle_consistency),
+        )
         .route("/api/v1/support/bundle", get(gateway::support_bundle))
+        .route("/metrics", get(metrics::handler))
         .route("/v1/chat/completions", post(gateway::chat_completions))
         .route("/v1/tools/prepare", post(tools::prepare))
         .route("/v1/tools/commit", post(tools::commit))
@@ -189,6 +326,10 @@ async fn main() {
         .layer(middleware::from_fn_with_state(
             limiter.clone(),
             rate_limit_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            cfg.trusted_proxies(),
+            client_middleware,
         ));
 
     let auth = middleware::from_fn({
@@ -223,15 +364,34 @@ async fn main() {
         headers.insert("pragma", HeaderValue::from_static("no-cache"));
         res
     });
-    let app = app.layer(auth).layer(sec);
+    let app = app
+        .layer(auth)
+        .layer(sec)
+        .layer(middleware::from_fn(metrics::middleware));
 
     let addr = cfg.bind_addr();
-    println!("Aegis Ultra listening on http://{}", addr);
+    tracing::info!(%addr, "Aegis Ultra listening");
 
     axum::serve(
         tokio::net::TcpListener::bind(addr).await.unwrap(),
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown(async move {
+        let mut term =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+        threats.close();
+    })
     .await
     .unwrap();
+
+    // the writer may still hold queued records
+    if let Err(e) = ledger.flush().await {
+        tracing::error!(error = %e, "audit flush on shutdown failed");
+    }
+    // the OTLP exporter's blocking client must not be torn down on a runtime thread
+    let _ = tokio::task::spawn_blocking(move || telemetry.shutdown()).await;
 }
@@ -0,0 +1,133 @@
+use axum::{
+    extract::MatchedPath,
+    http::{header::CONTENT_TYPE, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder, HistogramVec,
+    IntCounter, IntCounterVec, TextEncoder,
+};
+
+use crate::dlp;
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aegis_http_requests_total",
+        "HTTP requests by matched route, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap()
+});
+
+pub static DLP_FINDINGS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aegis_dlp_findings_total",
+        "DLP findings by direction, kind and pattern",
+        &["direction", "kind", "pattern"]
+    )
+    .unwrap()
+});
+
+pub static OPA_DECISIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aegis_opa_decisions_total",
+        "OPA decisions by outcome (allow, deny)",
+        &["decision"]
+    )
+    .unwrap()
+});
+
+pub static OPA_ERRORS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aegis_opa_errors_total",
+        "OPA queries that failed before a decision"
+    )
+    .unwrap()
+});
+
+pub static UPSTREAM_LATENCY: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "aegis_upstream_request_duration_seconds",
+        "Time until the upstream answered with headers, by outcome",
+        &["outcome"],
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap()
+});
+
+pub static TOOL_COMMITS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aegis_tool_commits_total",
+        "Executed tool commits by tool and exit code",
+        &["tool_id", "exit_code"]
+    )
+    .unwrap()
+});
+
+pub static APPROVAL_VERIFICATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aegis_approval_verifications_total",
+        "Approval token verifications by result",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static RATE_LIMITED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aegis_rate_limited_total",
+        "Requests rejected by the rate limiter"
+    )
+    .unwrap()
+});
+
+pub static AUDIT_WRITE_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "aegis_audit_write_failures_total",
+        "Audit records or fsyncs that failed"
+    )
+    .unwrap()
+});
+
+/// Registers the unlabelled metrics so they are scraped as 0 before anything
+/// happens.
+pub fn init() {
+    Lazy::force(&OPA_ERRORS);
+    Lazy::force(&RATE_LIMITED);
+    Lazy::force(&AUDIT_WRITE_FAILURES);
+}
+
+pub fn dlp_findings(direction: &str, findings: &[dlp::Finding]) {
+    for f in findings {
+        let kind = serde_json::to_value(f.kind)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
+        DLP_FINDINGS
+            .with_label_values(&[direction, &kind, &f.pattern])
+            .inc();
+    }
+}
+
+pub async fn middleware(req: Request<axum::body::Body>, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let res = next.run(req).await;
+    HTTP_REQUESTS
+        .with_label_values(&[&route, &method, res.status().as_str()])
+        .inc();
+    res
+}
+
+pub async fn handler() -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut buf = vec![];
+    let _ = encoder.encode(&prometheus::gather(), &mut buf);
+    ([(CONTENT_TYPE, encoder.format_type().to_string())], buf)
+}
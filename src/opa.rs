@@ -1,4 +1,8 @@
 use serde_json::Value;
+
+use tracing::Instrument;
+
+use crate::{metrics, telemetry};
 use thiserror::Error;
 #[derive(Debug, Error)]
 pub enum OpaError {
@@ -20,16 +24,34 @@ impl OpaClient {
         }
     }
     pub async fn require_allow(&self, data_path: &str, input: Value) -> Result<Value, OpaError> {
+        let r = self.query(data_path, input).await;
+        match &r {
+            Ok(_) => metrics::OPA_DECISIONS.with_label_values(&["allow"]).inc(),
+            Err(OpaError::Denied(reason)) => {
+                tracing::info!(path = data_path, reason, "opa denied");
+                metrics::OPA_DECISIONS.with_label_values(&["deny"]).inc()
+            }
+            Err(OpaError::Http(e)) => {
+                tracing::warn!(path = data_path, error = %e, "opa query failed");
+                metrics::OPA_ERRORS.inc()
+            }
+        }
+        r
+    }
+    async fn query(&self, data_path: &str, input: Value) -> Result<Value, OpaError> {
         let url = format!(
             "{}/v1/data/{}",
             self.base.trim_end_matches('/'),
             data_path.trim_start_matches('/')
         );
+        let span = tracing::info_span!("opa.query", otel.kind = "client", opa.path = data_path);
         let res = self
             .http
             .post(url)
+            .headers(span.in_scope(telemetry::outgoing_headers))
             .json(&serde_json::json!({"input":input}))
             .send()
+            .instrument(span)
             .await
             .map_err(|e| OpaError::Http(e.to_string()))?;
         let status = res.status();
@@ -0,0 +1,386 @@
+use axum::{body::Body, http::StatusCode, response::Response};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use std::collections::BTreeMap;
+use tokio::sync::mpsc;
+
+use crate::{audit, config::AppState, dlp, metrics, vault::PurgeOnDrop};
+
+// Only the tail of each field's output is rescanned on every delta; it has to be
+// wider than the longest pattern (injection rules allow 200 chars between keywords).
+const SCAN_WINDOW_BYTES: usize = 8 * 1024;
+// Output is sent this far behind what has been scanned; it has to be wider than
+// the longest a pattern can get before it matches.
+const HOLD_BYTES: usize = 512;
+
+/// `purge` drops the request's vault entries once the stream is done, which is
+/// long after the handler has returned.
+pub fn proxy_chat_stream(
+    st: AppState,
+    request_id: String,
+    upstream: reqwest::Response,
+    purge: PurgeOnDrop,
+) -> Response {
+    let (tx, rx) = mpsc::channel::<Bytes>(16);
+    let pump = async move {
+        let _purge = purge;
+        pump(st, request_id, upstream, tx).await
+    };
+    // the pump outlives the handler, so carry the HTTP request id over explicitly
+    match audit::HTTP_REQUEST_ID.try_with(|id| id.clone()) {
+        Ok(id) => tokio::spawn(audit::HTTP_REQUEST_ID.scope(id, pump)),
+        Err(_) => tokio::spawn(pump),
+    };
+    let body = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|b| (Ok::<_, std::convert::Infallible>(b), rx))
+    });
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/event-stream")
+        .body(Body::from_stream(body))
+        .unwrap()
+}
+
+async fn pump(
+    st: AppState,
+    request_id: String,
+    upstream: reqwest::Response,
+    tx: mpsc::Sender<Bytes>,
+) {
+    let mut chunks = upstream.bytes_stream();
+    let mut pending: Vec<u8> = vec![];
+    let mut relay = Relay::new(st.policy.tokenize_redactions);
+    loop {
+        let chunk = match chunks.next().await {
+            Some(Ok(c)) => c,
+            Some(Err(e)) => {
+                // the stream ends here either way, so a failed audit write changes nothing
+                let _ = st
+                    .ledger
+                    .append(
+                        "upstream.error",
+                        &request_id,
+                        serde_json::json!({"error": e.to_string(), "stream": true}),
+                    )
+                    .await;
+                let _ = tx
+                    .send(error_event(
+                        &request_id,
+                        "upstream_error",
+                        "upstream error",
+                        "upstream_error",
+                    ))
+                    .await;
+                return;
+            }
+            None => break,
+        };
+        pending.extend_from_slice(&chunk);
+        while let Some(end) = event_end(&pending) {
+            let event: Vec<u8> = pending.drain(..end).collect();
+            if !forward_event(&st, &request_id, &mut relay, event, &tx).await {
+                return;
+            }
+        }
+    }
+    if !pending.is_empty() && !forward_event(&st, &request_id, &mut relay, pending, &tx).await {
+        return;
+    }
+    // everything still held has been scanned with the rest of its field
+    let rest = relay.flush(&st, &request_id, None);
+    if !rest.is_empty() {
+        let _ = tx.send(Bytes::from(rest)).await;
+    }
+}
+
+/// Whether the upstream answered with an SSE body; anything else is handled
+/// as a complete JSON response.
+pub fn is_event_stream(res: &reqwest::Response) -> bool {
+    res.headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+/// Scans one SSE event and forwards what of it is cleared, or terminates the
+/// stream with a policy error. Returns false once the stream must stop.
+async fn forward_event(
+    st: &AppState,
+    request_id: &str,
+    relay: &mut Relay,
+    event: Vec<u8>,
+    tx: &mpsc::Sender<Bytes>,
+) -> bool {
+    let text = String::from_utf8_lossy(&event);
+    let mut out = String::new();
+    let mut lines = vec![];
+    for line in text.trim_end().lines() {
+        let data = line.strip_prefix("data:").map(str::trim);
+        if data == Some("[DONE]") {
+            out.push_str(&relay.flush(st, request_id, None));
+            lines.push(line.to_string());
+            continue;
+        }
+        let chunk = data.and_then(|d| serde_json::from_str::<serde_json::Value>(d).ok());
+        let Some(mut chunk) = chunk.filter(|v| v["choices"].is_array()) else {
+            // anything but a delta chunk is scanned on its own and passed on as is
+            let findings = blocking(st, line);
+            if !findings.is_empty() {
+                deny(st, request_id, &findings, tx).await;
+                return false;
+            }
+            lines.push(line.to_string());
+            continue;
+        };
+        let mut deltas = delta_fields(&mut chunk);
+        for (key, field, _) in &deltas {
+            relay.push(*key, field.as_str().unwrap_or_default());
+        }
+        for (key, _, _) in &deltas {
+            let findings = blocking(st, relay.window(*key));
+            if !findings.is_empty() {
+                deny(st, request_id, &findings, tx).await;
+                return false;
+            }
+        }
+        for (key, field, done) in deltas.iter_mut() {
+            **field = relay.release(st, request_id, *key, *done).into();
+        }
+        let finished: Vec<u64> = chunk["choices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|c| !c["finish_reason"].is_null())
+            .map(|c| c["index"].as_u64().unwrap_or(0))
+            .collect();
+        // whatever is still held belongs to fields this last chunk did not carry
+        for i in finished {
+            out.push_str(&relay.flush(st, request_id, Some(i)));
+        }
+        lines.push(format!("data: {}", chunk));
+    }
+    out.push_str(&lines.join("\n"));
+    out.push_str("\n\n");
+    tx.send(Bytes::from(out)).await.is_ok()
+}
+
+/// The findings in `text` that end the stream.
+fn blocking(st: &AppState, text: &str) -> Vec<dlp::Finding> {
+    let mut findings = st.dlp.engine().scan(text, &st.policy);
+    findings.retain(|f| {
+        f.action == dlp::RuleAction::Block
+            && matches!(
+                f.kind,
+                dlp::FindingKind::Secret | dlp::FindingKind::PromptInjection
+            )
+    });
+    findings
+}
+
+async fn deny(
+    st: &AppState,
+    request_id: &str,
+    findings: &[dlp::Finding],
+    tx: &mpsc::Sender<Bytes>,
+) {
+    metrics::dlp_findings("response", findings);
+    let reason = findings[0].kind.deny_reason();
+    let _ = st
+        .ledger
+        .append(
+            "response.stream.deny",
+            request_id,
+            serde_json::json!({"reason": reason, "findings": findings}),
+        )
+        .await;
+    let _ = tx
+        .send(error_event(
+            request_id,
+            "policy_violation",
+            "Blocked by policy",
+            reason,
+        ))
+        .await;
+}
+
+/// (choice index, tool call index or None for content)
+type FieldKey = (u64, Option<u64>);
+
+/// The streamed strings of a chunk, content and tool call arguments, with
+/// whether their choice finishes in it.
+fn delta_fields(chunk: &mut serde_json::Value) -> Vec<(FieldKey, &mut serde_json::Value, bool)> {
+    let mut out = vec![];
+    for choice in chunk["choices"].as_array_mut().into_iter().flatten() {
+        let i = choice["index"].as_u64().unwrap_or(0);
+        let done = !choice["finish_reason"].is_null();
+        let Some(delta) = choice.get_mut("delta").and_then(|d| d.as_object_mut()) else {
+            continue;
+        };
+        for (k, v) in delta.iter_mut() {
+            match k.as_str() {
+                "content" if v.is_string() => out.push(((i, None), v, done)),
+                "tool_calls" => {
+                    for (j, call) in v.as_array_mut().into_iter().flatten().enumerate() {
+                        let k = call["index"].as_u64().unwrap_or(j as u64);
+                        if let Some(a) = call
+                            .pointer_mut("/function/arguments")
+                            .filter(|a| a.is_string())
+                        {
+                            out.push(((i, Some(k)), a, done));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    out
+}
+
+#[derive(Default)]
+struct Field {
+    /// tail of everything streamed, rescanned on every delta
+    window: String,
+    /// streamed but not sent yet
+    held: String,
+}
+
+/// Relays each streamed field `HOLD_BYTES` behind what has been scanned, so a
+/// match still arriving across deltas is caught before any of it is sent.
+/// With tokenization, vault values are put back into what is sent; a
+/// placeholder split across deltas stays held until it is complete.
+struct Relay {
+    fields: BTreeMap<FieldKey, Field>,
+    detokenize: bool,
+}
+
+impl Relay {
+    fn new(detokenize: bool) -> Self {
+        Self {
+            fields: BTreeMap::new(),
+            detokenize,
+        }
+    }
+
+    fn push(&mut self, key: FieldKey, s: &str) {
+        let field = self.fields.entry(key).or_default();
+        field.window.push_str(s);
+        trim_window(&mut field.window);
+        field.held.push_str(s);
+    }
+
+    fn window(&self, key: FieldKey) -> &str {
+        self.fields.get(&key).map_or("", |f| &f.window)
+    }
+
+    /// The held text of `key` that is clear to send; all of it once the field is
+    /// `done`.
+    fn release(&mut self, st: &AppState, request_id: &str, key: FieldKey, done: bool) -> String {
+        let Some(field) = self.fields.get_mut(&key) else {
+            return String::new();
+        };
+        let mut cut = match done {
+            true => field.held.len(),
+            false => field.held.len().saturating_sub(HOLD_BYTES),
+        };
+        while !field.held.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if !done && self.detokenize {
+            cut = partial_placeholder(&field.held[..cut]).unwrap_or(cut);
+        }
+        let text: String = field.held.drain(..cut).collect();
+        if done {
+            self.fields.remove(&key);
+        }
+        match self.detokenize {
+            true => st.vault.detokenize(request_id, &text),
+            false => text,
+        }
+    }
+
+    /// Held text of one choice (or all) as delta events of their own.
+    fn flush(&mut self, st: &AppState, request_id: &str, choice: Option<u64>) -> String {
+        let keys: Vec<_> = self
+            .fields
+            .keys()
+            .filter(|(i, _)| choice.is_none_or(|c| c == *i))
+            .copied()
+            .collect();
+        let mut out = String::new();
+        for key in keys {
+            let text = self.release(st, request_id, key, true);
+            if text.is_empty() {
+                continue;
+            }
+            let delta = match key.1 {
+                None => serde_json::json!({"content": text}),
+                Some(k) => serde_json::json!({
+                    "tool_calls": [{"index": k, "function": {"arguments": text}}]
+                }),
+            };
+            let chunk = serde_json::json!({"choices": [{"index": key.0, "delta": delta}]});
+            out.push_str(&format!("data: {}\n\n", chunk));
+        }
+        out
+    }
+}
+
+/// Start of a trailing `<<LABEL_hex>>` placeholder that is not complete yet.
+fn partial_placeholder(text: &str) -> Option<usize> {
+    let from = text.len().saturating_sub(64);
+    text.match_indices('<')
+        .map(|(i, _)| i)
+        .filter(|&i| i >= from)
+        .find(|&i| {
+            let rest = &text.as_bytes()[i..];
+            let label = rest
+                .iter()
+                .skip(2)
+                .take_while(|b| b.is_ascii_uppercase())
+                .count();
+            let tail = rest.get(2 + label..).unwrap_or_default();
+            let hex = tail
+                .iter()
+                .skip(1)
+                .take_while(|b| b.is_ascii_hexdigit())
+                .count();
+            match rest.len() {
+                1 => true,
+                _ if rest[1] != b'<' => false,
+                _ if tail.is_empty() => true,
+                _ if tail[0] != b'_' || label == 0 => false,
+                _ => matches!(&tail[1 + hex..], [] | [b'>']),
+            }
+        })
+}
+
+fn event_end(buf: &[u8]) -> Option<usize> {
+    let lf = buf.windows(2).position(|w| w == b"\n\n").map(|i| i + 2);
+    let crlf = buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4);
+    match (lf, crlf) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn trim_window(window: &mut String) {
+    if window.len() <= SCAN_WINDOW_BYTES {
+        return;
+    }
+    let mut cut = window.len() - SCAN_WINDOW_BYTES;
+    while !window.is_char_boundary(cut) {
+        cut += 1;
+    }
+    window.drain(..cut);
+}
+
+fn error_event(request_id: &str, kind: &str, message: &str, code: &str) -> Bytes {
+    let body = serde_json::json!({
+        "error": {"message": message, "type": kind, "code": code},
+        "request_id": request_id
+    });
+    Bytes::from(format!("event: error\ndata: {}\n\n", body))
+}
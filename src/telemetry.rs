@@ -0,0 +1,133 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::TracerProvider as _,
+    Context, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::{audit, logging};
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if value.is_empty() {
+            return;
+        }
+        if let (Ok(k), Ok(v)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(k, v);
+        }
+    }
+}
+
+/// Keeps the tracer provider alive; `shutdown` flushes spans still batched.
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!(error = %e, "otlp shutdown failed");
+        }
+    }
+}
+
+/// Installs the tracing subscriber: redacted logs on stdout, JSON unless
+/// `log_format` is `text`, filtered by `AEGIS_LOG`. Spans always get W3C trace
+/// context so it can be propagated; they are exported only when
+/// `otlp_endpoint` (an OTLP/HTTP traces URL such as
+/// `http://127.0.0.1:4318/v1/traces`) is set.
+pub fn init(
+    otlp_endpoint: Option<&str>,
+    instance_id: &str,
+    log_format: &str,
+) -> Result<Telemetry, String> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let resource = Resource::builder()
+        .with_service_name("aegis-ultra")
+        .with_attribute(KeyValue::new(
+            "service.instance.id",
+            instance_id.to_string(),
+        ))
+        .build();
+    let mut builder = SdkTracerProvider::builder().with_resource(resource);
+    if let Some(endpoint) = otlp_endpoint {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| format!("otlp exporter: {}", e))?;
+        builder = builder.with_batch_exporter(exporter);
+    }
+    let provider = builder.build();
+    let otel = tracing_opentelemetry::layer().with_tracer(provider.tracer("aegis-ultra"));
+    let json = log_format != "text";
+    let json_logs = json.then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(logging::Redacting)
+    });
+    let text_logs = (!json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(logging::Redacting)
+    });
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_env("AEGIS_LOG").unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(otel)
+        .with(json_logs)
+        .with(text_logs)
+        .try_init()
+        .map_err(|e| format!("tracing init: {}", e))?;
+    Ok(Telemetry { provider })
+}
+
+/// Continues the caller's trace when the request carries a `traceparent`.
+pub fn set_remote_parent(span: &tracing::Span, headers: &HeaderMap) {
+    let cx: Context = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+    let _ = span.set_parent(cx);
+}
+
+/// Headers for an outgoing call made while serving a request: the ledger's
+/// `x-request-id`, the caller's `x-correlation-id` if it sent one, and a
+/// `traceparent` naming the current span.
+pub fn outgoing_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let cx = tracing::Span::current().context();
+    global::get_text_map_propagator(|p| p.inject_context(&cx, &mut HeaderInjector(&mut headers)));
+    let request_id = audit::REQUEST_ID.try_with(|id| id.clone()).ok();
+    if let Some(Ok(v)) = request_id.as_deref().map(HeaderValue::from_str) {
+        headers.insert("x-request-id", v);
+    }
+    // without a caller id the HTTP one is the request id itself
+    let correlation_id = audit::HTTP_REQUEST_ID
+        .try_with(|id| id.clone())
+        .ok()
+        .filter(|id| Some(id) != request_id.as_ref());
+    if let Some(Ok(v)) = correlation_id.as_deref().map(HeaderValue::from_str) {
+        headers.insert("x-correlation-id", v);
+    }
+    headers
+}
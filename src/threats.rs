@@ -0,0 +1,334 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock, RwLockReadGuard},
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::broadcast;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use uuid::Uuid;
+
+use crate::{client::ClientInfo, config::AppState, dlp::Finding};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Threat {
+    pub id: String,
+    pub ts: String,
+    pub severity: String,
+    pub rule: String,
+    pub src_ip: String,
+    pub dst_ip: String,
+    pub action: String,
+    pub reason: String,
+    #[serde(default)]
+    pub request_id: String,
+    #[serde(default)]
+    pub principal: Option<String>,
+    /// DLP patterns that matched
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// one entry per distinct matched value
+    #[serde(default)]
+    pub evidence: Vec<Evidence>,
+}
+
+/// What a threat keeps of a DLP match: never the value itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Evidence {
+    pub pattern: String,
+    pub preview: String,
+    pub value_hmac: String,
+}
+
+impl Threat {
+    fn at(&self) -> Option<OffsetDateTime> {
+        OffsetDateTime::parse(&self.ts, &Rfc3339).ok()
+    }
+}
+
+/// Windows a summary can be asked for; the largest one is kept in memory.
+pub const WINDOWS: [(&str, Duration); 3] = [
+    ("1h", Duration::from_secs(3600)),
+    ("24h", Duration::from_secs(24 * 3600)),
+    ("7d", Duration::from_secs(7 * 24 * 3600)),
+];
+
+pub fn parse_window(s: &str) -> Option<Duration> {
+    WINDOWS.iter().find(|(name, _)| *name == s).map(|(_, d)| *d)
+}
+
+/// Upper bound on threats held in memory, whatever their age.
+const MAX_IN_MEMORY: usize = 100_000;
+
+/// Appends between two passes dropping expired threats from the file; a
+/// quiet store gets one on its first append a day after the last.
+const COMPACT_EVERY: u64 = 1_000;
+
+/// Every recorded threat, one JSON object per line, plus the last 7 days of
+/// them in memory for listing and summaries. Threats older than `retain_days`
+/// are dropped from the file when the store is opened and as it grows.
+pub struct ThreatStore {
+    path: PathBuf,
+    retain_days: i64,
+    /// appends since the last compaction, and when that was
+    appended: u64,
+    compacted_at: OffsetDateTime,
+    recent: VecDeque<Threat>,
+}
+
+/// Rewrites the threat file without the threats older than `retain_days` (or
+/// unreadable); returns the ones kept.
+fn compact(path: &Path, retain_days: i64, now: OffsetDateTime) -> Result<Vec<Threat>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("read {}: {}", path.display(), e)),
+    };
+    let retain = time::Duration::days(retain_days);
+    let fresh = |t: &Threat| retain_days == 0 || t.at().is_some_and(|at| now - at <= retain);
+    let mut kept = vec![];
+    let mut dropped = 0usize;
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str::<Threat>(line) {
+            Ok(t) if fresh(&t) => kept.push(t),
+            _ => dropped += 1,
+        }
+    }
+    if dropped > 0 {
+        let mut out = String::new();
+        for t in &kept {
+            out.push_str(&serde_json::to_string(t).unwrap_or_default());
+            out.push('\n');
+        }
+        let tmp = path.with_extension("jsonl.tmp");
+        fs::write(&tmp, out)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| format!("write {}: {}", path.display(), e))?;
+    }
+    Ok(kept)
+}
+
+impl ThreatStore {
+    pub fn open(path: &Path, retain_days: i64) -> Result<Self, String> {
+        let now = OffsetDateTime::now_utc();
+        let kept = compact(path, retain_days, now)?;
+        let mut store = Self {
+            path: path.to_path_buf(),
+            retain_days,
+            appended: 0,
+            compacted_at: now,
+            recent: kept.into(),
+        };
+        store.prune(now);
+        Ok(store)
+    }
+
+    fn prune(&mut self, now: OffsetDateTime) {
+        let keep = WINDOWS[WINDOWS.len() - 1].1;
+        while self.recent.len() > MAX_IN_MEMORY
+            || self
+                .recent
+                .front()
+                .is_some_and(|t| t.at().is_none_or(|at| now - at > keep))
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, t: Threat) {
+        match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+        {
+            Ok(mut f) => {
+                let line = format!("{}\n", serde_json::to_string(&t).unwrap_or_default());
+                if let Err(e) = f.write_all(line.as_bytes()) {
+                    tracing::error!(path = %self.path.display(), error = %e, "threat store write failed");
+                }
+            }
+            Err(e) => {
+                tracing::error!(path = %self.path.display(), error = %e, "threat store open failed")
+            }
+        }
+        self.recent.push_back(t);
+        let now = OffsetDateTime::now_utc();
+        self.prune(now);
+        self.appended += 1;
+        if self.retain_days > 0
+            && (self.appended >= COMPACT_EVERY || now - self.compacted_at >= time::Duration::DAY)
+        {
+            self.appended = 0;
+            self.compacted_at = now;
+            if let Err(e) = compact(&self.path, self.retain_days, now) {
+                tracing::error!(error = %e, "threat store compaction failed");
+            }
+        }
+    }
+
+    /// Newest first.
+    pub fn latest(&self, limit: usize) -> Vec<Threat> {
+        self.recent.iter().rev().take(limit).cloned().collect()
+    }
+
+    fn within(&self, window: Duration) -> impl Iterator<Item = &Threat> {
+        let from = OffsetDateTime::now_utc() - window;
+        self.recent
+            .iter()
+            .rev()
+            .take_while(move |t| t.at().is_some_and(|at| at >= from))
+    }
+
+    pub fn count(&self, window: Duration, severities: &[&str]) -> usize {
+        self.within(window)
+            .filter(|t| severities.is_empty() || severities.contains(&t.severity.as_str()))
+            .count()
+    }
+
+    pub fn summary(&self, window: Duration) -> serde_json::Value {
+        let mut total = 0u64;
+        let mut by: [BTreeMap<String, u64>; 4] = Default::default();
+        for t in self.within(window) {
+            total += 1;
+            let src = if t.src_ip.is_empty() {
+                "unknown"
+            } else {
+                &t.src_ip
+            };
+            let principal = t.principal.as_deref().unwrap_or("unknown");
+            for (map, key) in
+                by.iter_mut()
+                    .zip([t.severity.as_str(), t.rule.as_str(), src, principal])
+            {
+                *map.entry(key.to_string()).or_insert(0) += 1;
+            }
+        }
+        let [sev, rule, src, principal] = by;
+        serde_json::json!({
+            "total": total,
+            "bySeverity": sev,
+            "byRule": rule,
+            "bySource": src,
+            "byPrincipal": principal,
+        })
+    }
+}
+
+/// The request a threat was raised for.
+pub struct ThreatSource<'a> {
+    pub client: &'a ClientInfo,
+    pub request_id: &'a str,
+    pub model: Option<&'a str>,
+}
+
+/// Publishes a blocked request as a threat.
+pub fn record(
+    st: &AppState,
+    src: &ThreatSource<'_>,
+    sev: &str,
+    rule: &str,
+    reason: &str,
+    findings: &[Finding],
+) {
+    let mut patterns: Vec<String> = findings.iter().map(|f| f.pattern.clone()).collect();
+    patterns.sort();
+    patterns.dedup();
+    let mut evidence: Vec<Evidence> = vec![];
+    for f in findings {
+        let e = Evidence {
+            pattern: f.pattern.clone(),
+            preview: f.preview.clone(),
+            value_hmac: f.value_hmac.clone(),
+        };
+        if !evidence.contains(&e) {
+            evidence.push(e);
+        }
+    }
+    let dst_ip = reqwest::Url::parse(&st.policy.upstream_base_url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default();
+    tracing::warn!(severity = sev, rule, reason, "threat blocked");
+    st.threats.publish(Threat {
+        id: format!("t-{}", Uuid::new_v4()),
+        ts: OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| "now".into()),
+        severity: sev.to_string(),
+        rule: rule.to_string(),
+        src_ip: src.client.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+        dst_ip,
+        action: "blocked".to_string(),
+        reason: reason.to_string(),
+        request_id: src.request_id.to_string(),
+        principal: src.client.principal.clone(),
+        patterns,
+        model: src.model.map(str::to_string),
+        evidence,
+    });
+}
+
+/// Threats waiting for the slowest subscriber before it starts losing them.
+const BUS_CAPACITY: usize = 4096;
+
+/// Fan-out for recorded threats. Publishing never blocks: the store, alert
+/// sinks and live streams each consume the bus at their own pace.
+pub struct ThreatBus {
+    tx: broadcast::Sender<Threat>,
+    store: Arc<RwLock<ThreatStore>>,
+    closed: CancellationToken,
+}
+
+impl ThreatBus {
+    /// Starts the thread that keeps `store` up to date from the bus.
+    pub fn start(store: ThreatStore) -> Self {
+        let (tx, mut rx) = broadcast::channel(BUS_CAPACITY);
+        let store = Arc::new(RwLock::new(store));
+        let sink = store.clone();
+        std::thread::Builder::new()
+            .name("threat-store".into())
+            .spawn(move || loop {
+                match rx.blocking_recv() {
+                    Ok(t) => sink.write().unwrap().record(t),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!(missed = n, "threat store fell behind; threats not stored")
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            })
+            .expect("spawn threat-store thread");
+        Self {
+            tx,
+            store,
+            closed: CancellationToken::new(),
+        }
+    }
+
+    pub fn publish(&self, t: Threat) {
+        // the store thread holds a receiver, so this only fails after shutdown
+        let _ = self.tx.send(t);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Threat> {
+        self.tx.subscribe()
+    }
+
+    /// Ends live streams so a graceful shutdown is not held open by them.
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    pub fn closed(&self) -> WaitForCancellationFutureOwned {
+        self.closed.clone().cancelled_owned()
+    }
+
+    pub fn store(&self) -> RwLockReadGuard<'_, ThreatStore> {
+        self.store.read().unwrap()
+    }
+}
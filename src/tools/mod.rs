@@ -1,13 +1,16 @@
 pub mod registry;
 pub mod sandbox;
 
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use time::OffsetDateTime;
-use uuid::Uuid;
 
-use crate::{approvals, config::AppState};
+use crate::{approvals, audit, client::ClientInfo, config::AppState, metrics, threats};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Risk {
@@ -38,6 +41,10 @@ pub struct PrepareRecord {
     pub prepare_digest: String,
     pub intent_hash: String,
     pub policy_hash: String,
+    /// hash of the DLP packs `policy_hash` covers, to tell a pack reload
+    /// between prepare and commit from tampering
+    #[serde(default)]
+    pub dlp_packs_hash: String,
     pub intent: ToolIntent,
     pub created_at: i64,
 }
@@ -97,8 +104,13 @@ fn canonical_bytes(v: &serde_json::Value) -> Vec<u8> {
     serde_json::to_vec(&canon(v)).unwrap_or_default()
 }
 
-fn compute_policy_hash(st: &AppState) -> String {
-    hash_sha256(&st.policy_raw)
+/// Covers the policy file and the DLP rule packs loaded with it.
+fn compute_policy_hash(st: &AppState, dlp_packs_hash: &str) -> String {
+    let body = serde_json::json!({
+        "policy": hash_sha256(&st.policy_raw),
+        "dlp_packs": dlp_packs_hash,
+    });
+    hash_sha256(&canonical_bytes(&body))
 }
 
 fn compute_intent_hash(intent: &ToolIntent) -> String {
@@ -137,17 +149,18 @@ async fn write_decision_file(st: &AppState, request_id: &str, decision: serde_js
 
 pub async fn prepare(
     State(st): State<AppState>,
+    Extension(crate::RequestId(request_id)): Extension<crate::RequestId>,
     Json(req): Json<PrepareReq>,
-) -> (StatusCode, Json<serde_json::Value>) {
+) -> Result<(StatusCode, Json<serde_json::Value>), audit::AuditError> {
     if st.policy.tool_prepare_allows_execution {
-        return (
+        return Ok((
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({"error":"policy invalid: prepare cannot execute"})),
-        );
+        ));
     }
 
-    let request_id = Uuid::new_v4().to_string();
-    let policy_hash = compute_policy_hash(&st);
+    let dlp_packs_hash = st.dlp.engine().hash().to_string();
+    let policy_hash = compute_policy_hash(&st, &dlp_packs_hash);
     let intent_hash = compute_intent_hash(&req.intent);
     let created_at = OffsetDateTime::now_utc().unix_timestamp();
     let prepare_digest = compute_prepare_digest(
@@ -161,21 +174,23 @@ pub async fn prepare(
         .tool_registry
         .is_allowlisted(&req.intent.params.tool_id, &req.intent.params.args);
     if st.policy.fail_closed && !allowlisted {
-        st.ledger.append(
-            "tool.prepare.denied",
-            &request_id,
-            serde_json::json!({"reason":"not_allowlisted"}),
-        );
+        st.ledger
+            .append(
+                "tool.prepare.denied",
+                &request_id,
+                serde_json::json!({"reason":"not_allowlisted"}),
+            )
+            .await?;
         write_decision_file(
             &st,
             &request_id,
             serde_json::json!({"allowed":false,"phase":"prepare","reason":"not_allowlisted"}),
         )
         .await;
-        return (
+        return Ok((
             StatusCode::FORBIDDEN,
             Json(serde_json::json!({"error":"tool not allowlisted","request_id":request_id})),
-        );
+        ));
     }
 
     if let Some(opa) = &st.opa {
@@ -187,50 +202,53 @@ pub async fn prepare(
             "approval": { "valid": false }
         });
         if let Err(e) = opa.require_allow(&st.opa_path, input).await {
-            st.ledger.append(
-                "tool.prepare.denied",
-                &request_id,
-                serde_json::json!({"reason": e.to_string()}),
-            );
+            st.ledger
+                .append(
+                    "tool.prepare.denied",
+                    &request_id,
+                    serde_json::json!({"reason": e.to_string()}),
+                )
+                .await?;
             write_decision_file(
                 &st,
                 &request_id,
                 serde_json::json!({"allowed":false,"phase":"prepare","reason":e.to_string()}),
             )
             .await;
-            return (
+            return Ok((
                 StatusCode::FORBIDDEN,
                 Json(serde_json::json!({"error":"tool prepare denied","request_id":request_id})),
-            );
+            ));
         }
     }
 
-    {
-        let mut map = st.prepares.write().await;
-        map.insert(
-            request_id.clone(),
-            PrepareRecord {
-                request_id: request_id.clone(),
-                prepare_digest: prepare_digest.clone(),
-                intent_hash: intent_hash.clone(),
-                policy_hash: policy_hash.clone(),
-                intent: req.intent.clone(),
-                created_at,
-            },
-        );
-    }
-
-    st.ledger.append(
-        "tool.prepare",
-        &request_id,
-        serde_json::json!({
-            "prepare_digest": prepare_digest,
-            "intent_hash": intent_hash,
-            "policy_hash": policy_hash,
-            "allowlisted": allowlisted
-        }),
+    // request ids are minted per request, so this never replaces a pending prepare
+    st.prepares.write().await.insert(
+        request_id.clone(),
+        PrepareRecord {
+            request_id: request_id.clone(),
+            prepare_digest: prepare_digest.clone(),
+            intent_hash: intent_hash.clone(),
+            policy_hash: policy_hash.clone(),
+            dlp_packs_hash,
+            intent: req.intent.clone(),
+            created_at,
+        },
     );
 
+    st.ledger
+        .append(
+            "tool.prepare",
+            &request_id,
+            serde_json::json!({
+                "prepare_digest": prepare_digest,
+                "intent_hash": intent_hash,
+                "policy_hash": policy_hash,
+                "allowlisted": allowlisted
+            }),
+        )
+        .await?;
+
     write_decision_file(
         &st,
         &request_id,
@@ -244,7 +262,7 @@ pub async fn prepare(
     )
     .await;
 
-    (
+    Ok((
         StatusCode::OK,
         Json(serde_json::json!(PrepareResp {
             request_id,
@@ -252,38 +270,89 @@ pub async fn prepare(
             intent_hash,
             policy_hash
         })),
-    )
+    ))
+}
+
+fn commit_threat(
+    st: &AppState,
+    client: &ClientInfo,
+    request_id: &str,
+    sev: &str,
+    rule: &str,
+    reason: &str,
+) {
+    let src = threats::ThreatSource {
+        client,
+        request_id,
+        model: None,
+    };
+    threats::record(st, &src, sev, rule, reason, &[]);
 }
 
 pub async fn commit(
     State(st): State<AppState>,
+    client: Option<Extension<ClientInfo>>,
     Json(req): Json<CommitReq>,
-) -> (StatusCode, Json<serde_json::Value>) {
+) -> Result<(StatusCode, Json<serde_json::Value>), audit::AuditError> {
+    let client = client.map(|c| c.0).unwrap_or_default();
     let rec = {
         let map = st.prepares.read().await;
         map.get(&req.request_id).cloned()
     };
     let Some(rec) = rec else {
-        return (
+        return Ok((
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({"error":"unknown request_id"})),
-        );
+        ));
     };
 
     if rec.prepare_digest != req.prepare_digest {
-        st.ledger.append(
-            "tool.commit.denied",
+        st.ledger
+            .append(
+                "tool.commit.denied",
+                &req.request_id,
+                serde_json::json!({"reason":"prepare_digest_mismatch"}),
+            )
+            .await?;
+        write_decision_file(&st, &req.request_id, serde_json::json!({"allowed":false,"phase":"commit","reason":"prepare_digest_mismatch"})).await;
+        commit_threat(
+            &st,
+            &client,
             &req.request_id,
-            serde_json::json!({"reason":"prepare_digest_mismatch"}),
+            "critical",
+            "Tamper: Tool Intent",
+            "prepare_digest_mismatch",
         );
-        write_decision_file(&st, &req.request_id, serde_json::json!({"allowed":false,"phase":"commit","reason":"prepare_digest_mismatch"})).await;
-        return (
+        return Ok((
             StatusCode::FORBIDDEN,
             Json(serde_json::json!({"error":"prepare digest mismatch"})),
-        );
+        ));
     }
 
-    let policy_hash = compute_policy_hash(&st);
+    // a pack reload since prepare is routine, not tampering: prepare again
+    if st.dlp.engine().hash() != rec.dlp_packs_hash {
+        st.ledger
+            .append(
+                "tool.commit.denied",
+                &req.request_id,
+                serde_json::json!({"reason":"policy_reloaded"}),
+            )
+            .await?;
+        write_decision_file(
+            &st,
+            &req.request_id,
+            serde_json::json!({"allowed":false,"phase":"commit","reason":"policy_reloaded"}),
+        )
+        .await;
+        return Ok((
+            StatusCode::CONFLICT,
+            Json(
+                serde_json::json!({"error":"policy reloaded since prepare","reason":"policy_reloaded"}),
+            ),
+        ));
+    }
+
+    let policy_hash = compute_policy_hash(&st, &rec.dlp_packs_hash);
     let intent_hash_val = rec.intent_hash.clone();
     let recomputed = compute_prepare_digest(
         &intent_hash_val,
@@ -292,37 +361,57 @@ pub async fn commit(
         rec.created_at,
     );
     if recomputed != req.prepare_digest {
-        st.ledger.append(
-            "tool.commit.denied",
+        st.ledger
+            .append(
+                "tool.commit.denied",
+                &req.request_id,
+                serde_json::json!({"reason":"intent_or_policy_changed"}),
+            )
+            .await?;
+        write_decision_file(&st, &req.request_id, serde_json::json!({"allowed":false,"phase":"commit","reason":"intent_or_policy_changed"})).await;
+        commit_threat(
+            &st,
+            &client,
             &req.request_id,
-            serde_json::json!({"reason":"intent_or_policy_changed"}),
+            "critical",
+            "Tamper: Tool Intent",
+            "intent_or_policy_changed",
         );
-        write_decision_file(&st, &req.request_id, serde_json::json!({"allowed":false,"phase":"commit","reason":"intent_or_policy_changed"})).await;
-        return (
+        return Ok((
             StatusCode::FORBIDDEN,
             Json(serde_json::json!({"error":"intent/policy changed"})),
-        );
+        ));
     }
 
     let allowlisted = st
         .tool_registry
         .is_allowlisted(&rec.intent.params.tool_id, &rec.intent.params.args);
     if st.policy.fail_closed && !allowlisted {
-        st.ledger.append(
-            "tool.commit.denied",
-            &req.request_id,
-            serde_json::json!({"reason":"not_allowlisted"}),
-        );
+        st.ledger
+            .append(
+                "tool.commit.denied",
+                &req.request_id,
+                serde_json::json!({"reason":"not_allowlisted"}),
+            )
+            .await?;
         write_decision_file(
             &st,
             &req.request_id,
             serde_json::json!({"allowed":false,"phase":"commit","reason":"not_allowlisted"}),
         )
         .await;
-        return (
+        commit_threat(
+            &st,
+            &client,
+            &req.request_id,
+            "high",
+            "Deny: Tool Commit",
+            "not_allowlisted",
+        );
+        return Ok((
             StatusCode::FORBIDDEN,
             Json(serde_json::json!({"error":"tool not allowlisted"})),
-        );
+        ));
     }
 
     let needs_approval = approval_required(&rec.intent, &st.policy);
@@ -335,21 +424,29 @@ pub async fn commit(
                 && tok.payload.scope == rec.intent.params.tool_id;
         }
         if !approval_valid {
-            st.ledger.append(
-                "tool.commit.denied",
-                &req.request_id,
-                serde_json::json!({"reason":"approval_required"}),
-            );
+            st.ledger
+                .append(
+                    "tool.commit.denied",
+                    &req.request_id,
+                    serde_json::json!({"reason":"approval_required"}),
+                )
+                .await?;
             write_decision_file(
                 &st,
                 &req.request_id,
                 serde_json::json!({"allowed":false,"phase":"commit","reason":"approval_required"}),
             )
             .await;
-            return (
+            // a token that was presented but does not verify is a forgery attempt
+            let (sev, rule, reason) = match req.approval {
+                Some(_) => ("critical", "Tamper: Approval", "approval_invalid"),
+                None => ("high", "Deny: Tool Commit", "approval_required"),
+            };
+            commit_threat(&st, &client, &req.request_id, sev, rule, reason);
+            return Ok((
                 StatusCode::FORBIDDEN,
                 Json(serde_json::json!({"error":"approval required"})),
-            );
+            ));
         }
     }
 
@@ -362,55 +459,88 @@ pub async fn commit(
             "approval": { "valid": approval_valid }
         });
         if let Err(e) = opa.require_allow(&st.opa_path, input).await {
-            st.ledger.append(
-                "tool.commit.denied",
-                &req.request_id,
-                serde_json::json!({"reason": e.to_string()}),
-            );
+            st.ledger
+                .append(
+                    "tool.commit.denied",
+                    &req.request_id,
+                    serde_json::json!({"reason": e.to_string()}),
+                )
+                .await?;
             write_decision_file(
                 &st,
                 &req.request_id,
                 serde_json::json!({"allowed":false,"phase":"commit","reason":e.to_string()}),
             )
             .await;
-            return (
+            commit_threat(
+                &st,
+                &client,
+                &req.request_id,
+                "high",
+                "Deny: Tool Commit",
+                "opa_denied",
+            );
+            return Ok((
                 StatusCode::FORBIDDEN,
                 Json(serde_json::json!({"error":"tool commit denied"})),
-            );
+            ));
         }
     }
 
+    // the run must be on the ledger before the tool can have any effect
+    st.ledger
+        .append(
+            "tool.commit.start",
+            &req.request_id,
+            serde_json::json!({
+                "tool_id": rec.intent.params.tool_id,
+                "prepare_digest": rec.prepare_digest,
+                "approval_valid": approval_valid
+            }),
+        )
+        .await?;
     let out = crate::tools::sandbox::native::run(&st, &req.request_id, &rec.intent).await;
     match out {
         Ok(r) => {
-            st.ledger.append(
-                "tool.commit",
-                &req.request_id,
-                serde_json::json!({
-                    "exit_code": r.exit_code,
-                    "stdout_path": r.stdout_path,
-                    "stderr_path": r.stderr_path
-                }),
-            );
+            metrics::TOOL_COMMITS
+                .with_label_values(&[&rec.intent.params.tool_id, &r.exit_code.to_string()])
+                .inc();
+            st.ledger
+                .append(
+                    "tool.commit",
+                    &req.request_id,
+                    serde_json::json!({
+                        "exit_code": r.exit_code,
+                        "stdout_path": r.stdout_path,
+                        "stderr_path": r.stderr_path
+                    }),
+                )
+                .await?;
             write_decision_file(
                 &st,
                 &req.request_id,
                 serde_json::json!({"allowed":true,"phase":"commit","exit_code":r.exit_code}),
             )
             .await;
-            (StatusCode::OK, Json(serde_json::json!(r)))
+            Ok((StatusCode::OK, Json(serde_json::json!(r))))
         }
         Err(e) => {
-            st.ledger.append(
-                "tool.commit.error",
-                &req.request_id,
-                serde_json::json!({"error": e}),
-            );
+            tracing::error!(tool_id = %rec.intent.params.tool_id, error = %e, "tool execution failed");
+            metrics::TOOL_COMMITS
+                .with_label_values(&[&rec.intent.params.tool_id, "error"])
+                .inc();
+            st.ledger
+                .append(
+                    "tool.commit.error",
+                    &req.request_id,
+                    serde_json::json!({"error": e}),
+                )
+                .await?;
             write_decision_file(&st, &req.request_id, serde_json::json!({"allowed":false,"phase":"commit","reason":"exec_failed","detail":e})).await;
-            (
+            Ok((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(serde_json::json!({"error":"execution failed"})),
-            )
+            ))
         }
     }
 }
@@ -138,7 +138,7 @@ pub async fn index() -> impl IntoResponse {
           <a class="link" href="#">View all</a>
         </div>
         <table id="threatTable">
-          <thead><tr><th>Severity</th><th>Rule</th><th>Source</th><th>Time</th></tr></thead>
+          <thead><tr><th>Severity</th><th>Rule</th><th>Source</th><th>Principal</th><th>Model</th><th>Time</th></tr></thead>
           <tbody></tbody>
         </table>
       </div>
@@ -160,8 +160,16 @@ async function fetchJson(url){
   catch(e){ console.warn("fetch fail", url, e); return null; }
 }
 function sevBadge(sev){
-  const cls = sev === "critical" ? "critical" : sev === "high" ? "high" : "medium";
-  return `<span class="sev ${cls}">${sev}</span>`;
+  const span = document.createElement("span");
+  span.className = "sev " + (sev === "critical" ? "critical" : sev === "high" ? "high" : "medium");
+  span.textContent = sev ?? "";
+  return span;
+}
+function cell(text, title){
+  const td = document.createElement("td");
+  td.textContent = text ?? "-";
+  if(title) td.title = title;
+  return td;
 }
 async function loadStatus(){
   const h = await fetchJson("/api/v1/health");
@@ -183,33 +191,48 @@ async function loadStatus(){
     document.getElementById("openPorts").textContent = "32";
   }
 }
+function threatRow(t){
+  const tr = document.createElement("tr");
+  // every field is caller-influenced, so cells are filled as text, never markup
+  const sev = document.createElement("td");
+  sev.appendChild(sevBadge(t.severity));
+  tr.append(sev, cell(t.rule, (t.patterns||[]).join(", ")), cell(t.src_ip), cell(t.principal), cell(t.model), cell(t.ts, t.request_id));
+  return tr;
+}
 async function loadThreats(){
   const data = await fetchJson("/api/v1/threats?limit=5");
   const tbody = document.querySelector("#threatTable tbody");
   if(!tbody) return;
   tbody.innerHTML = "";
   if(data && Array.isArray(data)){
-    data.forEach(t=>{
-      const tr = document.createElement("tr");
-      tr.innerHTML = `<td>${sevBadge(t.severity)}</td><td>${t.rule}</td><td>${t.src_ip}</td><td>${t.ts}</td>`;
-      tbody.appendChild(tr);
-    });
+    data.forEach(t=> tbody.appendChild(threatRow(t)));
   }
 }
+function streamThreats(){
+  const es = new EventSource("/api/v1/threats/stream");
+  es.addEventListener("threat", e=>{
+    const tbody = document.querySelector("#threatTable tbody");
+    if(!tbody) return;
+    tbody.insertBefore(threatRow(JSON.parse(e.data)), tbody.firstChild);
+    while(tbody.children.length > 5) tbody.removeChild(tbody.lastChild);
+    loadStatus();
+  });
+  es.addEventListener("lagged", ()=> loadThreats());
+}
 async function loadAudit(){
   const data = await fetchJson("/api/v1/audit?limit=12");
   const box = document.getElementById("audit");
   if(!box) return;
   box.innerHTML = "";
-  if(data && data.lines){
-    data.lines.forEach(l=>{
+  if(data && data.events){
+    data.events.forEach(ev=>{
       const div = document.createElement("div");
-      div.textContent = l;
+      div.textContent = `#${ev.seq} ${ev.event_type} ${ev.request_id} ${(ev.hash||"").slice(0,12)}`;
       box.appendChild(div);
     });
   }
 }
-loadStatus(); loadThreats(); loadAudit();
+loadStatus(); loadThreats(); loadAudit(); streamThreats();
 </script>
 </body>
 </html>"##,
@@ -0,0 +1,112 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+use dashmap::DashMap;
+use std::sync::Arc;
+
+use crate::dlp::{self, Finding, FindingKind};
+
+struct Sealed {
+    placeholder: String,
+    tag: [u8; 32],
+    nonce: Nonce,
+    ciphertext: Vec<u8>,
+}
+
+/// Holds the original values behind tokenized redactions for the lifetime of one
+/// request. Values are sealed with a per-process key that is never persisted, so
+/// nothing in here survives a restart.
+pub struct TokenVault {
+    cipher: ChaCha20Poly1305,
+    tag_key: [u8; 32],
+    entries: DashMap<String, Vec<Sealed>>,
+}
+
+impl TokenVault {
+    pub fn new() -> Self {
+        let tag_key: [u8; 32] = ChaCha20Poly1305::generate_key(&mut OsRng).into();
+        Self {
+            cipher: ChaCha20Poly1305::new(&ChaCha20Poly1305::generate_key(&mut OsRng)),
+            tag_key,
+            entries: DashMap::new(),
+        }
+    }
+
+    /// Replaces every secret/PII finding in `text` with a placeholder that is stable
+    /// for the same value, e.g. `<<PII_3f2a>>`, and seals the value under `request_id`.
+    pub fn tokenize(&self, request_id: &str, text: &str, findings: &[Finding]) -> String {
+        let mut sealed = self.entries.entry(request_id.to_string()).or_default();
+        dlp::replace_spans(text, findings, |f, value| {
+            let label = match f.kind {
+                FindingKind::Secret => "SECRET",
+                FindingKind::Pii => "PII",
+                _ => return None,
+            };
+            let tag = *blake3::keyed_hash(&self.tag_key, value.as_bytes()).as_bytes();
+            if let Some(s) = sealed.iter().find(|s| s.tag == tag) {
+                return Some(s.placeholder.clone());
+            }
+            // 4 hex chars, widened only if another value already owns them
+            let hex = hex::encode(tag);
+            let mut len = 4;
+            while sealed
+                .iter()
+                .any(|s| s.placeholder == format!("<<{}_{}>>", label, &hex[..len]))
+            {
+                len += 2;
+            }
+            let placeholder = format!("<<{}_{}>>", label, &hex[..len]);
+            let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+            let ciphertext = self.cipher.encrypt(&nonce, value.as_bytes()).ok()?;
+            sealed.push(Sealed {
+                placeholder: placeholder.clone(),
+                tag,
+                nonce,
+                ciphertext,
+            });
+            Some(placeholder)
+        })
+    }
+
+    /// Puts the original values back wherever the upstream echoed a placeholder.
+    pub fn detokenize(&self, request_id: &str, text: &str) -> String {
+        let Some(sealed) = self.entries.get(request_id) else {
+            return text.to_string();
+        };
+        let mut out = text.to_string();
+        for s in sealed.iter() {
+            if !out.contains(&s.placeholder) {
+                continue;
+            }
+            if let Ok(plain) = self.cipher.decrypt(&s.nonce, s.ciphertext.as_slice()) {
+                out = out.replace(&s.placeholder, &String::from_utf8_lossy(&plain));
+            }
+        }
+        out
+    }
+
+    pub fn purge(&self, request_id: &str) {
+        self.entries.remove(request_id);
+    }
+
+    pub fn purge_on_drop(self: &Arc<Self>, request_id: &str) -> PurgeOnDrop {
+        PurgeOnDrop {
+            vault: self.clone(),
+            request_id: request_id.to_string(),
+        }
+    }
+}
+
+/// Drops a request's sealed values once the handler is done with it, on every
+/// return path.
+pub struct PurgeOnDrop {
+    vault: Arc<TokenVault>,
+    request_id: String,
+}
+
+impl Drop for PurgeOnDrop {
+    fn drop(&mut self) {
+        self.vault.purge(&self.request_id);
+    }
+}